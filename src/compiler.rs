@@ -3,7 +3,27 @@ use std::collections::HashSet;
 use crate::syntaxtree as ST;
 use crate::syntaxtree::Expression as STExpression;
 use crate::interpreter;
-use interpreter::Instruction;
+use interpreter::{Instruction, ReversibilityCheck};
+
+
+#[derive(Debug, Default)]
+pub struct Options {
+    // Also emit runtime checks of the conditions that reversing relies on
+    pub strict: bool,
+    // Emit statement markers for the debugger to stop at
+    pub debug: bool
+}
+
+
+// The point just before a statement, which has a marker instruction in each stream that reaches
+// it. Positions are indices into fwd and bkwd, and the bkwd marker is lost if the backward code
+// is cleared
+#[derive(Clone, Debug)]
+struct StatementMarker {
+    fwd: Option<usize>,
+    bkwd: Option<usize>,
+    src_pos: (usize, usize)
+}
 
 
 #[derive(Clone, Default, Debug)]
@@ -11,7 +31,13 @@ pub struct Code {
     fwd: Vec<Instruction>,
     bkwd: Vec<Instruction>,
     f2b_links: Vec<(usize, usize)>,
-    b2f_links: Vec<(usize, usize)>
+    b2f_links: Vec<(usize, usize)>,
+    // The source (line, col) of the statement each instruction was compiled from, kept in step with fwd and bkwd
+    fwd_src_pos: Vec<Option<(usize, usize)>>,
+    bkwd_src_pos: Vec<Option<(usize, usize)>>,
+    markers: Vec<StatementMarker>,
+    // Pairs of (fwd, bkwd) indices of the instructions calling the same function in opposite directions
+    call_links: Vec<(usize, usize)>
 }
 
 impl Code {
@@ -24,38 +50,61 @@ impl Code {
             fwd: Vec::with_capacity(l1),
             bkwd: Vec::with_capacity(l2),
             f2b_links: Vec::new(),
-            b2f_links: Vec::new()
+            b2f_links: Vec::new(),
+            fwd_src_pos: Vec::with_capacity(l1),
+            bkwd_src_pos: Vec::with_capacity(l2),
+            markers: Vec::new(),
+            call_links: Vec::new()
         }
     }
 
     pub fn link_fwd2bkwd(&mut self) {
         self.f2b_links.push((self.fwd.len(), self.bkwd.len()));
         // Insert dummy instruction //
-        self.fwd.push(Instruction::Reverse{idx: 0});
+        self.push_fwd(Instruction::Reverse{idx: 0});
     }
     
     pub fn link_bkwd2fwd(&mut self) {
         self.b2f_links.push((self.bkwd.len(), self.fwd.len()));
         // Insert dummy instruction //
-        self.bkwd.push(Instruction::Reverse{idx: 0});
+        self.push_bkwd(Instruction::Reverse{idx: 0});
     }
 
     pub fn push_fwd(&mut self, x: Instruction) {
         self.fwd.push(x);
+        self.fwd_src_pos.push(None);
     }
 
     pub fn push_bkwd(&mut self, x: Instruction) {
         self.bkwd.push(x);
+        self.bkwd_src_pos.push(None);
     }
 
     pub fn append_fwd(&mut self, mut instructions: Vec<Instruction>) {
+        self.fwd_src_pos.resize(self.fwd_src_pos.len() + instructions.len(), None);
         self.fwd.append(&mut instructions);
     }
     
     pub fn append_bkwd(&mut self, instructions: Vec<Instruction>) {
+        self.bkwd_src_pos.resize(self.bkwd_src_pos.len() + instructions.len(), None);
         self.bkwd.extend(instructions.into_iter().rev());
     }
 
+    // Prefixes the code with a marker for the point just before the statement at src_pos
+    pub fn mark_statement(self, src_pos: (usize, usize)) -> Code {
+        let mut code = Code::new();
+        code.markers.push(StatementMarker{fwd: Some(0), bkwd: Some(0), src_pos});
+        code.push_fwd(Instruction::StatementMarker{idx: 0});
+        code.push_bkwd(Instruction::StatementMarker{idx: 0});
+        code.extend(self);
+        code
+    }
+
+    // Links the call instructions at the given indices into fwd and bkwd
+    pub fn link_call(&mut self, fwd: usize, bkwd: usize) {
+        self.call_links.push((fwd, bkwd));
+    }
+
     pub fn fwd_len(&mut self) -> usize {
         self.fwd.len()
     }
@@ -71,13 +120,35 @@ impl Code {
                 panic!("Internal inconsistency: clear_bkwd called on a Reverse instruction");
             }
         }
+        self.bkwd_src_pos.clear();
+        for marker in self.markers.iter_mut() {
+            marker.bkwd = None;
+        }
+        self.call_links.clear();
+    }
+
+    // Attributes every instruction not yet attributed to a (more deeply nested) statement to pos
+    pub fn set_src_pos(&mut self, pos: (usize, usize)) {
+        for src_pos in self.fwd_src_pos.iter_mut().chain(self.bkwd_src_pos.iter_mut()) {
+            if src_pos.is_none() {
+                *src_pos = Some(pos);
+            }
+        }
     }
 
     pub fn extend(&mut self, other: Code) {
-        let Code{fwd, bkwd, f2b_links, b2f_links} = other;
+        let Code{fwd, bkwd, f2b_links, b2f_links, fwd_src_pos, bkwd_src_pos, markers, call_links} = other;
         let (flen, blen) = (self.fwd.len(), self.bkwd.len());
+        for StatementMarker{fwd, bkwd, src_pos} in markers.into_iter() {
+            self.markers.push(StatementMarker{fwd: fwd.map(|f| f + flen), bkwd: bkwd.map(|b| b + blen), src_pos});
+        }
+        for (f, b) in call_links.into_iter() {
+            self.call_links.push((f + flen, b + blen));
+        }
         self.fwd.extend(fwd);
         self.bkwd.extend(bkwd);
+        self.fwd_src_pos.extend(fwd_src_pos);
+        self.bkwd_src_pos.extend(bkwd_src_pos);
         for (f, b) in f2b_links.into_iter() {
             self.f2b_links.push((f + flen, b + blen));
         }
@@ -97,17 +168,40 @@ impl Code {
         }
         self.bkwd.reverse();
         self.fwd.reverse();
+        self.bkwd_src_pos.reverse();
+        self.fwd_src_pos.reverse();
+        let (flen, blen) = (self.fwd.len(), self.bkwd.len());
+        let markers = self.markers.into_iter().map(|StatementMarker{fwd, bkwd, src_pos}| StatementMarker{
+            fwd: bkwd.map(|b| blen - 1 - b),
+            bkwd: fwd.map(|f| flen - 1 - f),
+            src_pos
+        }).collect();
+        let call_links = self.call_links.into_iter().map(|(f, b)| (blen - 1 - b, flen - 1 - f)).collect();
         Code{
             fwd: self.bkwd,
             bkwd: self.fwd,
             f2b_links: self.b2f_links,
-            b2f_links: self.f2b_links
+            b2f_links: self.f2b_links,
+            fwd_src_pos: self.bkwd_src_pos,
+            bkwd_src_pos: self.fwd_src_pos,
+            markers,
+            call_links
         }
     }
 
     pub fn finalise(code: Code) -> interpreter::Code {
-        let Code{mut fwd, mut bkwd, f2b_links, b2f_links} = code;
+        let Code{mut fwd, mut bkwd, f2b_links, b2f_links, fwd_src_pos, mut bkwd_src_pos, markers, call_links} = code;
         bkwd.reverse();
+        bkwd_src_pos.reverse();
+
+        // Number the markers, and move everything into the order bkwd is run in //
+        let markers: Vec<_> = markers.into_iter().enumerate().map(|(idx, StatementMarker{fwd: f, bkwd: b, src_pos})| {
+            let b = b.map(|b| bkwd.len() - 1 - b);
+            if let Some(ip) = f {fwd[ip] = Instruction::StatementMarker{idx}};
+            if let Some(ip) = b {bkwd[ip] = Instruction::StatementMarker{idx}};
+            interpreter::Marker{src_pos, fwd_ip: f, bkwd_ip: b}
+        }).collect();
+        let call_links = call_links.into_iter().map(|(f, b)| (f, bkwd.len() - 1 - b)).collect();
 
         // Compute instruction pointers for reversals //
         for (f, b) in f2b_links.into_iter() {
@@ -160,7 +254,7 @@ impl Code {
                 _ => {}
             }
         }
-        interpreter::Code{fwd, bkwd}
+        interpreter::Code{fwd, bkwd, fwd_src_pos, bkwd_src_pos, markers, call_links}
     }
 }
 
@@ -183,11 +277,36 @@ impl ST::Expression for ST::StringNode {
     }
 }
 
+impl ST::Expression for ST::FunctionRefNode {
+    fn is_mono(&self) -> bool {false}
+    fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
+    fn function_name(&self) -> Option<&str> {Some(&self.name)}
+
+    fn compile(&self) -> Vec<Instruction> {
+        vec![Instruction::LoadConst{idx: self.const_idx}]
+    }
+}
+
 impl ST::Expression for ST::LookupNode {
     fn is_mono(&self) -> bool {self.is_mono}
     fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
 
     fn compile(&self) -> Vec<Instruction> {
+        self.compile_subscripts(self.indices.len(), false)
+    }
+
+    fn compile_ref(&self) -> Vec<Instruction> {
+        self.compile_subscripts(self.indices.len(), true)
+    }
+}
+
+impl ST::LookupNode {
+    // Leaves the last index on the stack beneath the item it indexes into
+    fn compile_container(&self) -> Vec<Instruction> {
+        self.compile_subscripts(self.indices.len() - 1, true)
+    }
+
+    fn compile_subscripts(&self, size: usize, is_ref: bool) -> Vec<Instruction> {
         let mut instructions = Vec::with_capacity(self.indices.len()+1);        
         for index in self.indices.iter().rev() {
             instructions.extend(index.compile());
@@ -199,8 +318,10 @@ impl ST::Expression for ST::LookupNode {
             instructions.push(Instruction::LoadRegister{register:self.register});
         }
 
-        if !self.indices.is_empty() {
-            instructions.push(Instruction::Subscript{size: self.indices.len()});
+        if size > 0 && is_ref {
+            instructions.push(Instruction::SubscriptRef{size});
+        } else if size > 0 {
+            instructions.push(Instruction::Subscript{size});
         }
         instructions
     }
@@ -247,6 +368,44 @@ impl ST::Expression for ST::UniopNode {
     }
 }
 
+impl ST::Expression for ST::CallExprNode {
+    fn is_mono(&self) -> bool {self.is_mono}
+    fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
+
+    fn compile(&self) -> Vec<Instruction> {
+        let mut ret = Vec::new();
+        for arg in self.borrow_args.iter().rev() {
+            ret.extend(arg.compile());
+        }
+        ret.extend(self.callee.compile(true));
+
+        // Bennett-style cleanup: uncall using a copy of the result, leaving the original on the stack
+        ret.push(Instruction::DuplicateRef);
+        ret.push(Instruction::UniqueVar);
+        for arg in self.borrow_args.iter() {
+            ret.extend(arg.compile());
+        }
+        ret.extend(self.callee.compile(false));
+        ret
+    }
+}
+
+impl ST::Expression for ST::TernaryNode {
+    fn is_mono(&self) -> bool {self.is_mono}
+    fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
+
+    fn compile(&self) -> Vec<Instruction> {
+        let if_expr = self.if_expr.compile();
+        let else_expr = self.else_expr.compile();
+        let mut ret = self.condition.compile();
+        ret.push(Instruction::RelativeJumpIfFalse{delta: (if_expr.len() + 2) as isize});
+        ret.extend(if_expr);
+        ret.push(Instruction::RelativeJump{delta: (else_expr.len() + 1) as isize});
+        ret.extend(else_expr);
+        ret
+    }
+}
+
 impl ST::Expression for ST::ArrayLiteralNode {
     fn is_mono(&self) -> bool {self.is_mono}
     fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
@@ -261,6 +420,36 @@ impl ST::Expression for ST::ArrayLiteralNode {
     }
 }
 
+impl ST::Expression for ST::RecordLiteralNode {
+    fn is_mono(&self) -> bool {self.is_mono}
+    fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
+
+    fn compile(&self) -> Vec<Instruction> {
+        let mut ret = Vec::new();
+        for (name_idx, value) in self.fields.iter() {
+            ret.extend(value.compile());
+            ret.push(Instruction::LoadConst{idx: *name_idx});
+        }
+        ret.push(Instruction::RecordLiteral{size: self.fields.len()});
+        ret
+    }
+}
+
+impl ST::Expression for ST::MapLiteralNode {
+    fn is_mono(&self) -> bool {self.is_mono}
+    fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
+
+    fn compile(&self) -> Vec<Instruction> {
+        let mut ret = Vec::new();
+        for (key, value) in self.entries.iter() {
+            ret.extend(value.compile());
+            ret.extend(key.compile());
+        }
+        ret.push(Instruction::MapLiteral{size: self.entries.len()});
+        ret
+    }
+}
+
 impl ST::Expression for ST::ArrayRepeatNode {
     fn is_mono(&self) -> bool {self.is_mono}
     fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
@@ -278,8 +467,9 @@ impl ST::Expression for ST::ArrayRepeatNode {
 
 impl ST::Statement for ST::PrintNode {
     fn is_mono(&self) -> bool {true}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
 
-    fn compile(&self) -> Code {
+    fn compile(&self, _options: &Options) -> Code {
         let mut count = self.items.len() as isize;
         if self.newline {count *= -1};
 
@@ -303,10 +493,23 @@ impl ST::Statement for ST::PrintNode {
 
 impl ST::Statement for ST::LetUnletNode {
     fn is_mono(&self) -> bool {self.is_mono}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
+
+    fn compile(&self, options: &Options) -> Code {
+        // In strict mode, check the variable still matches the expression before removing it
+        let check = if options.strict && !self.is_mono {
+            let mut check = vec![Instruction::LoadRegister{register: self.register}];
+            check.extend(self.rhs.compile());
+            check.push(Instruction::BinopEq);
+            check.push(Instruction::CheckReversible{check: ReversibilityCheck::Unlet, expected: true});
+            check
+        } else {
+            Vec::new()
+        };
 
-    fn compile(&self) -> Code {
         let mut code = Code::new();
         if self.is_unlet {
+            code.append_fwd(check);
             code.push_fwd(Instruction::FreeRegister{register: self.register});
 
             code.push_bkwd(Instruction::StoreRegister{register: self.register});
@@ -318,6 +521,7 @@ impl ST::Statement for ST::LetUnletNode {
             code.push_fwd(Instruction::StoreRegister{register: self.register});
 
             code.push_bkwd(Instruction::FreeRegister{register: self.register});
+            code.append_bkwd(check);
         }
 
         if self.is_mono {code.clear_bkwd();}
@@ -328,9 +532,10 @@ impl ST::Statement for ST::LetUnletNode {
 
 impl ST::Statement for ST::RefUnrefNode {
     fn is_mono(&self) -> bool {self.is_mono}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
 
-    fn compile(&self) -> Code {
-        let mut create_ref = self.rhs.compile();
+    fn compile(&self, _options: &Options) -> Code {
+        let mut create_ref = self.rhs.compile_ref();
         create_ref.push(Instruction::StoreRegister{register: self.register});
         let remove_ref = vec![Instruction::FreeRegister{register: self.register}];
 
@@ -351,15 +556,17 @@ impl ST::Statement for ST::RefUnrefNode {
 
 impl ST::Statement for ST::ModopNode {
     fn is_mono(&self) -> bool {self.is_mono}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
 
-    fn compile(&self) -> Code {
-        let lookup = self.lookup.compile();
+    fn compile(&self, _options: &Options) -> Code {
+        let lookup = self.lookup.compile_ref();
         let rhs = self.rhs.compile();
         let bkwd_op = match self.op {
             Instruction::BinopAdd => Instruction::BinopSub,
             Instruction::BinopSub => Instruction::BinopAdd,
             Instruction::BinopMul => Instruction::BinopDiv,
             Instruction::BinopDiv => Instruction::BinopMul,
+            Instruction::BinopBitXor => Instruction::BinopBitXor,
             _ => unreachable!()
         };
 
@@ -385,24 +592,32 @@ impl ST::Statement for ST::ModopNode {
 
 impl ST::Statement for ST::PushPullNode {
     fn is_mono(&self) -> bool {self.is_mono}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
     
-    fn compile(&self) -> Code {
+    fn compile(&self, _options: &Options) -> Code {
         let mut code = Code::new();
-        let lookup = self.lookup.compile();
         let register = self.register;
 
+        // An indexed push/pull is resolved at runtime, since a map inserts/removes the key
+        // whereas anything else pushes to / pulls from the indexed item
+        let (lookup, push, pull) = if self.lookup.indices.is_empty() {
+            (self.lookup.compile_ref(), Instruction::Push{register}, Instruction::Pull{register})
+        } else {
+            (self.lookup.compile_container(), Instruction::PushIndexed{register}, Instruction::PullIndexed{register})
+        };
+
         if self.is_push {
             code.append_fwd(lookup.clone());
-            code.push_fwd(Instruction::Push{register});
+            code.push_fwd(push);
     
-            code.push_bkwd(Instruction::Pull{register});
+            code.push_bkwd(pull);
             code.append_bkwd(lookup);
 
         } else {
             code.append_fwd(lookup.clone());
-            code.push_fwd(Instruction::Pull{register});
+            code.push_fwd(pull);
     
-            code.push_bkwd(Instruction::Push{register});
+            code.push_bkwd(push);
             code.append_bkwd(lookup);
         }
         
@@ -412,19 +627,44 @@ impl ST::Statement for ST::PushPullNode {
 }
 
 
+// Code evaluating a condition and checking it has the expected value, for strict mode
+fn condition_check(expr: &[Instruction], check: ReversibilityCheck, expected: bool) -> Vec<Instruction> {
+    let mut code = expr.to_vec();
+    code.push(Instruction::CheckReversible{check, expected});
+    code
+}
+
+// Follows a block with the given checks, run after the block in each direction
+fn check_after(block: Code, fwd_check: Vec<Instruction>, bkwd_check: Vec<Instruction>) -> Code {
+    let mut code = Code::new();
+    code.append_bkwd(bkwd_check);
+    code.extend(block);
+    code.append_fwd(fwd_check);
+    code
+}
+
+
 impl ST::Statement for ST::IfNode {
     fn is_mono(&self) -> bool {self.is_mono}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
     
-    fn compile(&self) -> Code {
+    fn compile(&self, options: &Options) -> Code {
         let fwd_expr = self.fwd_expr.compile();
         let bkwd_expr = self.bkwd_expr.compile();
         let mut if_block = Code::new();
         for stmt in self.if_stmts.iter() {
-            if_block.extend(stmt.compile());
+            if_block.extend(stmt.compile_with_src_pos(options));
         }
         let mut else_block = Code::new();
         for stmt in self.else_stmts.iter() {
-            else_block.extend(stmt.compile());
+            else_block.extend(stmt.compile_with_src_pos(options));
+        }
+        if options.strict && !self.is_mono {
+            let check = ReversibilityCheck::IfCondition;
+            if_block = check_after(
+                if_block, condition_check(&bkwd_expr, check, true), condition_check(&fwd_expr, check, true));
+            else_block = check_after(
+                else_block, condition_check(&bkwd_expr, check, false), condition_check(&fwd_expr, check, false));
         }
         let if_bkwd_len = if_block.bkwd_len() as isize;
         let else_bkwd_len = else_block.bkwd_len() as isize;
@@ -455,15 +695,26 @@ impl ST::Statement for ST::IfNode {
 
 impl ST::Statement for ST::WhileNode {
     fn is_mono(&self) -> bool {self.is_mono}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
     
-    fn compile(&self) -> Code {
+    fn compile(&self, options: &Options) -> Code {
         let fwd_expr = self.fwd_expr.compile();
         // The backward condition can be None if the loop is mono
         let bkwd_expr = self.bkwd_expr.as_ref().map(|e| e.compile());
         let mut stmts = Code::new();
         for stmt in self.stmts.iter() {
-            stmts.extend(stmt.compile());
+            stmts.extend(stmt.compile_with_src_pos(options));
         }
+        let (fwd_entry_check, bkwd_entry_check) = match &bkwd_expr {
+            Some(bkwd_expr) if options.strict && !self.is_mono => {
+                let check = ReversibilityCheck::WhileRepeat;
+                stmts = check_after(
+                    stmts, condition_check(bkwd_expr, check, true), condition_check(&fwd_expr, check, true));
+                let check = ReversibilityCheck::WhileEntry;
+                (condition_check(bkwd_expr, check, false), condition_check(&fwd_expr, check, false))
+            },
+            _ => (Vec::new(), Vec::new())
+        };
 
         let stmts_fwd_len = stmts.fwd_len() as isize;
         let stmts_bkwd_len = stmts.bkwd_len() as isize;
@@ -471,6 +722,7 @@ impl ST::Statement for ST::WhileNode {
 
         let mut code = Code::new();
         
+        code.append_fwd(fwd_entry_check);
         code.append_fwd(fwd_expr);
         
         code.push_fwd(Instruction::RelativeJumpIfFalse{
@@ -495,7 +747,7 @@ impl ST::Statement for ST::WhileNode {
             });
             code.append_bkwd(bkwd_expr);
         };
-
+        code.append_bkwd(bkwd_entry_check);
 
         if self.is_mono {code.clear_bkwd();}
         code
@@ -505,13 +757,14 @@ impl ST::Statement for ST::WhileNode {
 
 impl ST::Statement for ST::ForNode {
     fn is_mono(&self) -> bool {self.is_mono}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
     
-    fn compile(&self) -> Code {
-        let iter_lookup = self.iterator.compile();
+    fn compile(&self, options: &Options) -> Code {
+        let iter_lookup = self.iterator.compile_ref();
 
         let mut stmts = Code::new();
         for stmt in self.stmts.iter() {
-            stmts.extend(stmt.compile());
+            stmts.extend(stmt.compile_with_src_pos(options));
         }
         let stmts_fwd_len = stmts.fwd_len();
         let stmts_bkwd_len = stmts.bkwd_len();
@@ -519,7 +772,9 @@ impl ST::Statement for ST::ForNode {
         let mut code = Code::new();
         
         code.append_fwd(iter_lookup.clone());
-        code.push_fwd(Instruction::CreateIter{register: self.register});
+        code.push_fwd(Instruction::CreateIter{
+            register: self.register, index_register: self.index_register, over_keys: self.over_keys
+        });
         code.push_fwd(Instruction::StepIter{ip: stmts_fwd_len + 2});
         code.push_bkwd(Instruction::RelativeJump{delta: -(1 + stmts_bkwd_len as isize)});
 
@@ -527,7 +782,9 @@ impl ST::Statement for ST::ForNode {
 
         code.push_fwd(Instruction::RelativeJump{delta: -(1 + stmts_fwd_len as isize)});
         code.push_bkwd(Instruction::StepIter{ip: stmts_bkwd_len + 2});
-        code.push_bkwd(Instruction::CreateIter{register: self.register});
+        code.push_bkwd(Instruction::CreateIter{
+            register: self.register, index_register: self.index_register, over_keys: self.over_keys
+        });
         code.append_bkwd(iter_lookup);
         
         if self.is_mono {code.clear_bkwd();}
@@ -537,16 +794,17 @@ impl ST::Statement for ST::ForNode {
 
 impl ST::Statement for ST::DoYieldNode {
     fn is_mono(&self) -> bool {false}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
     
-    fn compile(&self) -> Code {
+    fn compile(&self, options: &Options) -> Code {
 
         let mut code = Code::new();
         for do_stmt in self.do_stmts.iter() {
-            code.extend(do_stmt.compile());
+            code.extend(do_stmt.compile_with_src_pos(options));
         }
         let undo_block = code.clone().reversed();
         for yield_stmt in self.yield_stmts.iter() {
-            code.extend(yield_stmt.compile());
+            code.extend(yield_stmt.compile_with_src_pos(options));
         }
         code.extend(undo_block);
         
@@ -555,10 +813,55 @@ impl ST::Statement for ST::DoYieldNode {
 }
 
 
+impl ST::Statement for ST::LocalNode {
+    fn is_mono(&self) -> bool {self.is_mono}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
+
+    fn compile(&self, options: &Options) -> Code {
+        let register = self.register;
+        let mut code = Code::new();
+
+        // In strict mode, check the variable matches the expression for the end being left before removing it
+        let check = |expr: &ST::ExpressionNode| if options.strict && !self.var_is_mono {
+            let mut check = vec![Instruction::LoadRegister{register}];
+            check.extend(expr.compile());
+            check.push(Instruction::BinopEq);
+            check.push(Instruction::CheckReversible{check: ReversibilityCheck::Delocal, expected: true});
+            check
+        } else {
+            Vec::new()
+        };
+
+        code.append_fwd(self.init.compile());
+        code.push_fwd(Instruction::UniqueVar);
+        code.push_fwd(Instruction::StoreRegister{register});
+        if !self.var_is_mono {
+            code.push_bkwd(Instruction::FreeRegister{register});
+            code.append_bkwd(check(&self.init));
+        }
+
+        for stmt in self.stmts.iter() {
+            code.extend(stmt.compile_with_src_pos(options));
+        }
+
+        code.append_fwd(check(&self.exit));
+        code.push_fwd(Instruction::FreeRegister{register});
+        if !self.var_is_mono {
+            code.push_bkwd(Instruction::StoreRegister{register});
+            code.push_bkwd(Instruction::UniqueVar);
+            code.append_bkwd(self.exit.compile());
+        }
+
+        if self.is_mono {code.clear_bkwd();}
+        code
+    }
+}
+
 impl ST::Statement for ST::CatchNode {
     fn is_mono(&self) -> bool {true}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
     
-    fn compile(&self) -> Code {
+    fn compile(&self, _options: &Options) -> Code {
         let mut code = Code::new();
         code.append_fwd(self.expr.compile());
         code.push_fwd(Instruction::RelativeJumpIfFalse{delta: 2});
@@ -567,39 +870,110 @@ impl ST::Statement for ST::CatchNode {
     }
 }
 
+impl ST::Statement for ST::AssertNode {
+    fn is_mono(&self) -> bool {true}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
+
+    fn compile(&self, _options: &Options) -> Code {
+        let assert = Instruction::Assert{line: self.line, col: self.col};
+        let mut code = Code::new();
+
+        code.append_fwd(self.expr.compile());
+        code.push_fwd(assert.clone());
+
+        code.push_bkwd(assert);
+        code.append_bkwd(self.expr.compile());
+
+        // Mono variables aren't restored when running backwards, so only check going forwards
+        if self.is_mono {code.clear_bkwd();}
+        code
+    }
+}
+
 impl ST::Statement for ST::CallNode {
     fn is_mono(&self) -> bool {self.is_mono}
+    fn get_src_pos(&self) -> (usize, usize) {(self.line, self.col)}
     
-    fn compile(&self) -> Code {
+    fn compile(&self, _options: &Options) -> Code {
+        let (fwd, bkwd) = if self.is_uncall {(
+            self.call_instructions(&self.stolen_args, &self.return_args, false),
+            self.call_instructions(&self.return_args, &self.stolen_args, true)
+        )} else {(
+            self.call_instructions(&self.stolen_args, &self.return_args, true),
+            self.call_instructions(&self.return_args, &self.stolen_args, false)
+        )};
+
+        // The callee is the last thing called, after any calls in the arguments
+        let is_call = |i: &Instruction| matches!(i,
+            Instruction::Call{..} | Instruction::Uncall{..} | Instruction::CallIndirect | Instruction::UncallIndirect);
+        let fwd_call = fwd.iter().rposition(is_call).unwrap();
+        let bkwd_call = bkwd.iter().rposition(is_call).unwrap();
+
         let mut code = Code::new();
+        code.link_call(fwd_call, bkwd.len() - 1 - bkwd_call);
+        code.append_fwd(fwd);
+        code.append_bkwd(bkwd);
 
-        for &register in self.stolen_args.iter().rev() {
-            code.push_fwd(Instruction::LoadRegister{register});
-            code.push_fwd(Instruction::FreeRegister{register});
-        }
+        if self.is_mono {code.clear_bkwd();}
+        code
+    }
+}
 
-        if self.is_uncall {
-            code.push_bkwd(Instruction::Call{idx: self.func_idx});
-            code.push_fwd(Instruction::Uncall{idx: self.func_idx});
-        } else {
+impl ST::CallNode {
+    // A call takes its inputs in reverse order and leaves its outputs in order on the stack,
+    // so an uncall must provide them the other way around
+    fn call_instructions(&self, inputs: &[usize], outputs: &[usize], forwards: bool) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+
+        if forwards {
+            for &register in inputs.iter().rev() {
+                instructions.push(Instruction::LoadRegister{register});
+                instructions.push(Instruction::FreeRegister{register});
+            }
             for arg in self.borrow_args.iter().rev() {
-                code.append_fwd(arg.compile());
+                instructions.extend(arg.compile_ref());
+            }
+            instructions.extend(self.callee.compile(true));
+            for &register in outputs.iter().rev() {
+                instructions.push(Instruction::StoreRegister{register});
+            }
+        } else {
+            for &register in inputs.iter() {
+                instructions.push(Instruction::LoadRegister{register});
+                instructions.push(Instruction::FreeRegister{register});
+            }
+            for arg in self.borrow_args.iter() {
+                instructions.extend(arg.compile_ref());
+            }
+            instructions.extend(self.callee.compile(false));
+            for &register in outputs.iter() {
+                instructions.push(Instruction::StoreRegister{register});
             }
-            code.push_fwd(Instruction::Call{idx: self.func_idx});
-            code.push_bkwd(Instruction::Uncall{idx: self.func_idx});
         }
 
-        for &register in self.return_args.iter().rev() {
-            code.push_fwd(Instruction::StoreRegister{register});
-        }
+        instructions
+    }
+}
 
-        if self.is_mono {code.clear_bkwd();}
-        code
+impl ST::Callee {
+    fn compile(&self, forwards: bool) -> Vec<Instruction> {
+        match self {
+            ST::Callee::Function(idx) => {
+                if forwards {vec![Instruction::Call{idx: *idx}]}
+                else        {vec![Instruction::Uncall{idx: *idx}]}
+            },
+            ST::Callee::Reference(lookup) => {
+                let mut instructions = lookup.compile();
+                if forwards {instructions.push(Instruction::CallIndirect)}
+                else        {instructions.push(Instruction::UncallIndirect)};
+                instructions
+            }
+        }
     }
 }
 
 impl ST::FunctionNode {
-    pub fn compile(&self) -> interpreter::Function {
+    pub fn compile(&self, options: &Options) -> interpreter::Function {
         let mut code = Code::new();
 
         for &register in &self.borrow_registers {
@@ -611,7 +985,7 @@ impl ST::FunctionNode {
         }
 
         for stmt in &self.stmts {
-            code.extend(stmt.compile());
+            code.extend(stmt.compile_with_src_pos(options));
         }
 
         for &register in &self.return_registers {
@@ -623,15 +997,19 @@ impl ST::FunctionNode {
         }
 
         interpreter::Function{
+            name: self.name.clone(),
+            file: self.file.clone(),
             consts: self.consts.clone(),
             code: Code::finalise(code),
-            num_registers: self.num_registers
+            num_registers: self.num_registers,
+            symbols: self.symbols.clone()
         }
     }
 
     // Compile as the special 'global function' which is run for the global scope before main
-    pub fn compile_to_global(&self) -> interpreter::Function {
-        let mut func = self.compile();
+    pub fn compile_to_global(&self, options: &Options) -> interpreter::Function {
+        let mut func = self.compile(options);
+        func.name = String::from("<global scope>");
         for instruction in func.code.fwd.iter_mut().chain(func.code.bkwd.iter_mut()) {
             match instruction {
                 interpreter::Instruction::LoadRegister{register} => {
@@ -648,12 +1026,40 @@ impl ST::FunctionNode {
 }
 
 impl ST::Module {
-    pub fn compile(&self) -> interpreter::Module {
+    pub fn compile(&self, options: &Options) -> interpreter::Module {
         let main_idx = self.main_idx;
-        let mut functions: Vec<_> = self.functions.iter().map(|f| f.compile()).collect();
+        let mut functions: Vec<_> = self.functions.iter().map(|f| f.compile(options)).collect();
         let global_func_idx = functions.len();
-        functions.push(self.global_func.compile_to_global());
+        functions.push(self.global_func.compile_to_global(options));
 
         interpreter::Module{main_idx, functions, global_func_idx}
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interpreter::Interpreter;
+    use crate::testing::{self, TempProgram};
+
+    fn run(source: &str, strict: bool) -> Result<(), String> {
+        let file = TempProgram::new(source);
+        let program = testing::compile(file.path(), &Options{strict, debug: false})?;
+        let options = interpreter::Options::default();
+        Interpreter::new(&program, &options).run_main(&program).map_err(|e| e.desc)
+    }
+
+    #[test]
+    fn strict_mode_checks_delocal_values() {
+        let wrong_exit = "fn main()() { local t = 4 { t += 1; } delocal t = 4; } ~main()";
+        assert_eq!(run(wrong_exit, false), Ok(()));
+        assert!(run(wrong_exit, true).unwrap_err().contains("delocal value"));
+
+        let right_exit = "fn main()() { local t = 4 { t += 1; } delocal t = 5; } ~main()";
+        assert_eq!(run(right_exit, true), Ok(()));
+
+        let wrong_init = "fn main()() { ~f(); } ~main()
+                          fn f()() { local t = 3 { t += 1; } delocal t = 5; } ~f()";
+        assert!(run(wrong_init, true).unwrap_err().contains("initial value"));
+    }
+}
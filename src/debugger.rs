@@ -0,0 +1,390 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use crate::interpreter::{ExecutionPoint, Interpreter, Module};
+
+
+// Writes a line of the debugger's own output. Like println!, failing to write it isn't worth stopping for
+macro_rules! say {
+    ($debugger:expr, $($arg:tt)*) => {{
+        let _ = writeln!($debugger.out, $($arg)*);
+    }};
+}
+
+
+const HELP: &str = "\
+Commands:
+    s,  step               Run to the next statement, stepping into calls
+    n,  next               Run to the next statement in this function or its callers
+    f,  finish             Run until this function returns
+    c,  continue           Run until a breakpoint or the end of main
+    rs, reverse-step       Undo back to the previous statement, stepping into calls
+    rn, reverse-next       Undo back to the previous statement in this function or its callers
+    rf, reverse-finish     Undo back to where this function was called
+    rc, reverse-continue   Undo back to a breakpoint or the start of main
+    b,  break [file:]line  Set a breakpoint
+    d,  delete [file:]line Remove a breakpoint
+    breakpoints            List the breakpoints
+    p,  print name         Print a variable in the current function or the global scope
+    locals                 Print the variables which exist at this point in the current function
+    bt, backtrace          Print the call stack
+    h,  help               Print this message
+    q,  quit               Stop debugging
+An empty line repeats the last command.";
+
+
+// How far a run command goes before pausing, other than at breakpoints
+#[derive(Clone, Copy, PartialEq)]
+enum Stop {
+    Step,
+    Next,
+    Finish,
+    Continue
+}
+
+// Where the program has got to between commands
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Paused,
+    EndOfMain,    // Run forwards to the end of main, which can still be undone
+    StartOfMain,  // Undone back to the start of main
+    Finished
+}
+
+
+pub struct Debugger<'a> {
+    interpreter: Interpreter<'a>,
+    main_file: String,
+    breakpoints: HashSet<(String, usize)>,
+    sources: HashMap<String, Vec<String>>,
+    state: State,
+    // Whether the program is being undone, in which case every function runs the opposite
+    // way to how it was called
+    rewinding: bool,
+    out: Box<dyn Write>
+}
+
+impl<'a> Debugger<'a> {
+
+    // Runs the global scope, then pauses before the first statement of main
+    pub fn new(interpreter: Interpreter<'a>, module: &Module, main_file: &str, out: Box<dyn Write>) -> Debugger<'a> {
+        let mut debugger = Debugger {
+            interpreter,
+            main_file: String::from(main_file),
+            breakpoints: HashSet::new(),
+            sources: HashMap::new(),
+            state: State::Paused,
+            rewinding: false,
+            out
+        };
+        match debugger.interpreter.execute() {
+            Ok(()) => {
+                debugger.interpreter.call(module.main_idx, true);
+                if debugger.interpreter.current_marker().is_some() {
+                    debugger.show_position();
+                } else {
+                    debugger.run(Stop::Step, false);
+                }
+            },
+            Err(error) => {
+                say!(debugger, "RuntimeError in the global scope:\n ->  {}", error.desc);
+                debugger.state = State::Finished;
+            }
+        }
+        debugger
+    }
+
+    pub fn repl(&mut self) {
+        self.read_commands(io::stdin().lock());
+    }
+
+    fn read_commands(&mut self, mut input: impl BufRead) {
+        let mut last_command = String::new();
+        loop {
+            let _ = write!(self.out, "(debug) ");
+            let _ = self.out.flush();
+            let mut line = String::new();
+            if input.read_line(&mut line).unwrap_or(0) == 0 {
+                break;
+            }
+            let line = match line.trim() {
+                "" => last_command.clone(),
+                line => String::from(line)
+            };
+            if !self.command(&line) {
+                break;
+            }
+            last_command = line;
+        }
+    }
+
+    // Carries out one command, returning false to quit
+    fn command(&mut self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let argument = words.next();
+        match (command, argument) {
+            ("s", None) | ("step", None) => self.run(Stop::Step, false),
+            ("n", None) | ("next", None) => self.run(Stop::Next, false),
+            ("f", None) | ("finish", None) => self.run(Stop::Finish, false),
+            ("c", None) | ("continue", None) => self.run(Stop::Continue, false),
+            ("rs", None) | ("reverse-step", None) => self.run(Stop::Step, true),
+            ("rn", None) | ("reverse-next", None) => self.run(Stop::Next, true),
+            ("rf", None) | ("reverse-finish", None) => self.run(Stop::Finish, true),
+            ("rc", None) | ("reverse-continue", None) => self.run(Stop::Continue, true),
+            ("b", Some(location)) | ("break", Some(location)) => {
+                if let Some(breakpoint) = self.parse_location(location) {
+                    say!(self, "Breakpoint at {}:{}", breakpoint.0, breakpoint.1);
+                    self.breakpoints.insert(breakpoint);
+                }
+            },
+            ("d", Some(location)) | ("delete", Some(location)) => {
+                if let Some(breakpoint) = self.parse_location(location) {
+                    if !self.breakpoints.remove(&breakpoint) {
+                        say!(self, "No breakpoint at {}:{}", breakpoint.0, breakpoint.1);
+                    }
+                }
+            },
+            ("breakpoints", None) => {
+                let mut breakpoints: Vec<_> = self.breakpoints.iter().collect();
+                breakpoints.sort();
+                for (file, line) in breakpoints {
+                    say!(self, "{}:{}", file, line);
+                }
+            },
+            ("p", Some(name)) | ("print", Some(name)) => {
+                match self.interpreter.lookup_variable(name) {
+                    Some(var) => say!(self, "{} = {}", name, var.borrow()),
+                    None => say!(self, "No variable \"{}\" in scope", name)
+                }
+            },
+            ("locals", None) => {
+                for (name, var) in self.interpreter.local_variables() {
+                    say!(self, "{} = {}", name, var.borrow());
+                }
+            },
+            ("bt", None) | ("backtrace", None) => {
+                // Skipping the global scope, which main was called from
+                for frame in self.interpreter.call_stack().iter().skip(1) {
+                    match frame.src_pos {
+                        Some((line, _)) => say!(self, "    {} at {}:{}", frame.function, frame.file, line),
+                        None => say!(self, "    {} in {}", frame.function, frame.file)
+                    }
+                }
+            },
+            ("h", None) | ("help", None) => say!(self, "{}", HELP),
+            ("q", None) | ("quit", None) => return false,
+            _ => say!(self, "Unrecognised command \"{}\", try \"help\"", line)
+        }
+        true
+    }
+
+    fn parse_location(&mut self, location: &str) -> Option<(String, usize)> {
+        let (file, line) = match location.rfind(':') {
+            Some(idx) => (&location[..idx], &location[idx + 1..]),
+            None => (self.main_file.as_str(), location)
+        };
+        match line.parse() {
+            Ok(line) => Some((String::from(file), line)),
+            Err(_) => {
+                say!(self, "Expected a line number, or a file and line number like main.mx:12");
+                None
+            }
+        }
+    }
+
+    fn run(&mut self, stop: Stop, rewind: bool) {
+        match self.state {
+            State::Finished => return say!(self, "The program has finished"),
+            State::StartOfMain if rewind => return say!(self, "Already at the start of main"),
+            State::EndOfMain if !rewind => {
+                // Let main return into the global scope, after which nothing can be undone
+                self.state = State::Finished;
+                if let Err(error) = self.interpreter.execute() {
+                    say!(self, "RuntimeError:\n ->  {}", error.desc);
+                }
+                return say!(self, "The program has finished");
+            },
+            _ => {}
+        }
+        if rewind != self.rewinding {
+            if let Err(desc) = self.interpreter.turn_around() {
+                return say!(self, "{}", desc);
+            }
+            self.rewinding = rewind;
+        }
+
+        let start_depth = self.interpreter.depth();
+        let breakpoints = &self.breakpoints;
+        let mut pause = |interpreter: &Interpreter<'a>, point: ExecutionPoint| match point {
+            ExecutionPoint::Statement{marker} => {
+                let function = interpreter.current_function();
+                let line = function.code.markers[marker].src_pos.0;
+                let depth = interpreter.depth();
+                let at_breakpoint = breakpoints.iter().any(|(file, breakpoint_line)| {
+                    *breakpoint_line == line && same_file(&function.file, file)
+                });
+                // The global scope has already run, but may call functions with statements
+                depth > 0 && (at_breakpoint || match stop {
+                    Stop::Step => true,
+                    Stop::Next => depth <= start_depth,
+                    Stop::Finish => depth < start_depth,
+                    Stop::Continue => false
+                })
+            },
+            // Pause before main returns into the global scope, so it can still be undone
+            ExecutionPoint::EndOfCode => interpreter.depth() == 1
+        };
+
+        match self.interpreter.execute_until(&mut pause) {
+            Ok(true) => {},
+            Ok(false) => {
+                self.state = State::Finished;
+                return say!(self, "The program has finished");
+            },
+            Err(error) => {
+                self.state = State::Finished;
+                let location = error.src_pos.map(|(line, col)| format!(" at line {}, column {}", line, col));
+                return say!(self, "RuntimeError in {}{}:\n ->  {}",
+                                error.function, location.unwrap_or_default(), error.desc);
+            }
+        }
+
+        self.state = match self.interpreter.current_marker() {
+            Some(_) => State::Paused,
+            None if self.rewinding => State::StartOfMain,
+            None => State::EndOfMain
+        };
+        self.show_position();
+    }
+
+    fn show_position(&mut self) {
+        let function = self.interpreter.current_function();
+        let marker = match (self.state, self.interpreter.current_marker()) {
+            (State::StartOfMain, _) => return say!(self, "At the start of main"),
+            (State::EndOfMain, _) => return say!(self, "At the end of main"),
+            (_, Some(marker)) => marker,
+            (_, None) => return
+        };
+        // An uncalled function is shown with the same ~ as the uncall
+        let name = if self.interpreter.is_forwards() != self.rewinding {function.name.clone()}
+                   else                                                {format!("~{}", function.name)};
+        let (line, _) = marker.src_pos;
+        say!(self, "{} at {}:{}", name, function.file, line);
+
+        let file = function.file.clone();
+        let source = self.sources.entry(file.clone()).or_insert_with(|| {
+            fs::read_to_string(&file).map(|text| text.lines().map(String::from).collect()).unwrap_or_default()
+        });
+        if let Some(text) = source.get(line - 1) {
+            say!(self, "{:>5} | {}", line, text);
+        }
+    }
+}
+
+
+// Whether a breakpoint's file refers to the file a function is in, allowing any leading directories to be left off
+fn same_file(function_file: &str, breakpoint_file: &str) -> bool {
+    function_file == breakpoint_file || function_file.ends_with(&format!("/{}", breakpoint_file))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+    use crate::interpreter::{Options, OutputBuffer};
+    use crate::testing::{self, TempProgram};
+
+    const PROGRAM: &str = "\
+fn main()() {
+    x = 1;
+    double(x);
+    y = x + 1;
+    println(y);
+} ~main()
+
+fn double(n)() {
+    n *= 2;
+} ~double()
+";
+
+    // Debugs the program with a script of commands, giving everything printed with the
+    // temporary file's path replaced by prog.mx
+    fn debug(commands: &str) -> String {
+        let file = TempProgram::new(PROGRAM);
+        let program = testing::compile(file.path(), &compiler::Options{strict: false, debug: true}).unwrap();
+        let options = Options::default();
+        let mut interpreter = Interpreter::new(&program, &options);
+        let output = OutputBuffer::new();
+        interpreter.output_to(Box::new(output.clone()));
+        let mut debugger = Debugger::new(interpreter, &program, file.path(), Box::new(output.clone()));
+        debugger.read_commands(commands.as_bytes());
+        output.contents().replace(file.path(), "prog.mx")
+    }
+
+    #[test]
+    fn stepping_forwards_and_backwards() {
+        let expected = "\
+main at prog.mx:2
+    2 |     x = 1;
+(debug) main at prog.mx:3
+    3 |     double(x);
+(debug) double at prog.mx:9
+    9 |     n *= 2;
+(debug) main at prog.mx:4
+    4 |     y = x + 1;
+(debug) x = 2
+(debug) double at prog.mx:9
+    9 |     n *= 2;
+(debug) main at prog.mx:3
+    3 |     double(x);
+(debug) x = 1
+(debug) main at prog.mx:2
+    2 |     x = 1;
+(debug) At the start of main
+(debug) Already at the start of main
+(debug) ";
+        assert_eq!(debug("s\ns\ns\np x\nrs\nrs\np x\nrs\nrc\nrs\nq\n"), expected);
+    }
+
+    #[test]
+    fn breakpoints_and_backtraces() {
+        let expected = "\
+main at prog.mx:2
+    2 |     x = 1;
+(debug) Breakpoint at prog.mx:9
+(debug) double at prog.mx:9
+    9 |     n *= 2;
+(debug)     main at prog.mx:3
+    double at prog.mx:9
+(debug) prog.mx:9
+(debug) (debug) No breakpoint at prog.mx:9
+(debug) At the start of main
+(debug) 3
+At the end of main
+(debug) The program has finished
+(debug) ";
+        assert_eq!(debug("b 9\nc\nbt\nbreakpoints\nd 9\nd 9\nrc\nc\nc\n"), expected);
+    }
+
+    #[test]
+    fn printing_variables() {
+        // An empty line repeats the last command
+        let expected = "\
+main at prog.mx:2
+    2 |     x = 1;
+(debug) main at prog.mx:3
+    3 |     double(x);
+(debug) main at prog.mx:4
+    4 |     y = x + 1;
+(debug) x = 2
+(debug) x = 2
+(debug) No variable \"nothing\" in scope
+(debug) Expected a line number, or a file and line number like main.mx:12
+(debug) Unrecognised command \"foo\", try \"help\"
+(debug) ";
+        assert_eq!(debug("n\n\np x\nlocals\np nothing\nb x\nfoo\nq\n"), expected);
+    }
+}
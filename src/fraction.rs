@@ -0,0 +1,340 @@
+extern crate num_rational;
+extern crate num_bigint;
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, Sub, Mul, Div, Rem, Neg};
+use std::str::FromStr;
+use num_traits::cast::ToPrimitive;
+use num_traits::identities::{Zero, One};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+
+
+// An exact rational number. Most values in a program are small integers, so anything whose
+// numerator and denominator fit in machine words is kept in them, and only promoted to a
+// BigRational when an operation overflows. Values are always normalised, with the
+// denominator positive and coprime to the numerator, and stored as Small whenever they fit,
+// so equal numbers always have equal representations
+#[derive(Clone, PartialEq, Eq)]
+pub enum Fraction {
+    Small(i64, i64),
+    Big(BigRational)
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        let remainder = a % b;
+        a = b;
+        b = remainder;
+    }
+    a
+}
+
+impl Fraction {
+    pub fn new(numer: BigInt, denom: BigInt) -> Fraction {
+        Fraction::from_big(BigRational::new(numer, denom))
+    }
+
+    pub fn from_integer(value: BigInt) -> Fraction {
+        match value.to_i64() {
+            Some(value) => Fraction::Small(value, 1),
+            None => Fraction::Big(BigRational::from_integer(value))
+        }
+    }
+
+    // Every Small result passes through here. Products of two i64s always fit in an i128,
+    // and so do sums of two such products
+    fn from_wide(numer: i128, denom: i128) -> Fraction {
+        if denom == 0 {
+            panic!("denominator == 0");
+        }
+        let divisor = gcd(numer.unsigned_abs(), denom.unsigned_abs()) as i128;
+        let (numer, denom) = if denom < 0 {(-numer / divisor, -denom / divisor)}
+                             else         {(numer / divisor, denom / divisor)};
+        match (i64::try_from(numer), i64::try_from(denom)) {
+            (Ok(numer), Ok(denom)) => Fraction::Small(numer, denom),
+            _ => Fraction::Big(BigRational::new_raw(BigInt::from(numer), BigInt::from(denom)))
+        }
+    }
+
+    fn from_big(value: BigRational) -> Fraction {
+        match (value.numer().to_i64(), value.denom().to_i64()) {
+            (Some(numer), Some(denom)) => Fraction::Small(numer, denom),
+            _ => Fraction::Big(value)
+        }
+    }
+
+    fn to_big(&self) -> BigRational {
+        match self {
+            Fraction::Small(numer, denom) => BigRational::new_raw(BigInt::from(*numer), BigInt::from(*denom)),
+            Fraction::Big(value) => value.clone()
+        }
+    }
+
+    pub fn numer(&self) -> BigInt {
+        match self {
+            Fraction::Small(numer, _) => BigInt::from(*numer),
+            Fraction::Big(value) => value.numer().clone()
+        }
+    }
+
+    pub fn denom(&self) -> BigInt {
+        match self {
+            Fraction::Small(_, denom) => BigInt::from(*denom),
+            Fraction::Big(value) => value.denom().clone()
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        match self {
+            Fraction::Small(_, denom) => *denom == 1,
+            Fraction::Big(value) => value.is_integer()
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Fraction::Small(numer, _) => *numer < 0,
+            Fraction::Big(value) => *value.numer() < BigInt::zero()
+        }
+    }
+
+    // Rounds towards zero
+    pub fn to_integer(&self) -> BigInt {
+        match self {
+            Fraction::Small(numer, denom) => BigInt::from(numer / denom),
+            Fraction::Big(value) => value.to_integer()
+        }
+    }
+
+    pub fn to_usize(&self) -> Option<usize> {
+        match self {
+            Fraction::Small(numer, denom) => (numer / denom).to_usize(),
+            Fraction::Big(value) => value.to_integer().to_usize()
+        }
+    }
+
+    pub fn trunc(&self) -> Fraction {
+        match self {
+            Fraction::Small(numer, denom) => Fraction::Small(numer / denom, 1),
+            Fraction::Big(value) => Fraction::from_big(value.trunc())
+        }
+    }
+
+    pub fn recip(&self) -> Fraction {
+        match self {
+            Fraction::Small(numer, denom) => Fraction::from_wide(*denom as i128, *numer as i128),
+            Fraction::Big(value) => Fraction::from_big(value.recip())
+        }
+    }
+}
+
+impl From<i64> for Fraction {
+    fn from(value: i64) -> Fraction {
+        Fraction::Small(value, 1)
+    }
+}
+
+impl FromStr for Fraction {
+    type Err = num_rational::ParseRatioError;
+
+    fn from_str(string: &str) -> Result<Fraction, Self::Err> {
+        BigRational::from_str(string).map(Fraction::from_big)
+    }
+}
+
+impl Zero for Fraction {
+    fn zero() -> Fraction {
+        Fraction::Small(0, 1)
+    }
+
+    fn is_zero(&self) -> bool {
+        matches!(self, Fraction::Small(0, _))
+    }
+}
+
+impl One for Fraction {
+    fn one() -> Fraction {
+        Fraction::Small(1, 1)
+    }
+}
+
+impl Ord for Fraction {
+    fn cmp(&self, other: &Fraction) -> Ordering {
+        match (self, other) {
+            (Fraction::Small(ln, ld), Fraction::Small(rn, rd)) => {
+                (*ln as i128 * *rd as i128).cmp(&(*rn as i128 * *ld as i128))
+            },
+            _ => self.to_big().cmp(&other.to_big())
+        }
+    }
+}
+
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Fraction) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fraction::Small(numer, 1) => write!(f, "{}", numer),
+            Fraction::Small(numer, denom) => write!(f, "{}/{}", numer, denom),
+            Fraction::Big(value) => write!(f, "{}", value)
+        }
+    }
+}
+
+impl fmt::Debug for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Neg for &Fraction {
+    type Output = Fraction;
+
+    fn neg(self) -> Fraction {
+        match self {
+            Fraction::Small(numer, denom) => Fraction::from_wide(-(*numer as i128), *denom as i128),
+            Fraction::Big(value) => Fraction::from_big(-value)
+        }
+    }
+}
+
+impl Neg for Fraction {
+    type Output = Fraction;
+
+    fn neg(self) -> Fraction {
+        -&self
+    }
+}
+
+// Implements a binop on pairs of borrowed or pairs of owned fractions. The small case
+// gets the numerators and denominators of both sides widened to i128
+macro_rules! fraction_binop {
+    ($trait:ident, $method:ident, |$ln:ident, $ld:ident, $rn:ident, $rd:ident| $small:expr) => {
+        impl $trait<&Fraction> for &Fraction {
+            type Output = Fraction;
+
+            fn $method(self, other: &Fraction) -> Fraction {
+                match (self, other) {
+                    (Fraction::Small(ln, ld), Fraction::Small(rn, rd)) => {
+                        let ($ln, $ld, $rn, $rd) = (*ln as i128, *ld as i128, *rn as i128, *rd as i128);
+                        $small
+                    },
+                    _ => Fraction::from_big(self.to_big().$method(other.to_big()))
+                }
+            }
+        }
+
+        impl $trait<Fraction> for Fraction {
+            type Output = Fraction;
+
+            fn $method(self, other: Fraction) -> Fraction {
+                (&self).$method(&other)
+            }
+        }
+    };
+}
+
+fraction_binop!(Add, add, |ln, ld, rn, rd| Fraction::from_wide(ln * rd + rn * ld, ld * rd));
+fraction_binop!(Sub, sub, |ln, ld, rn, rd| Fraction::from_wide(ln * rd - rn * ld, ld * rd));
+fraction_binop!(Mul, mul, |ln, ld, rn, rd| Fraction::from_wide(ln * rn, ld * rd));
+fraction_binop!(Div, div, |ln, ld, rn, rd| Fraction::from_wide(ln * rd, ld * rn));
+fraction_binop!(Rem, rem, |ln, ld, rn, rd| Fraction::from_wide((ln * rd) % (rn * ld), ld * rd));
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn big(value: i128) -> Fraction {
+        Fraction::Big(BigRational::from_integer(BigInt::from(value)))
+    }
+
+    #[test]
+    fn small_arithmetic_stays_small() {
+        let half = Fraction::Small(1, 2);
+        let third = Fraction::Small(1, 3);
+        assert_eq!(&half + &third, Fraction::Small(5, 6));
+        assert_eq!(&half - &third, Fraction::Small(1, 6));
+        assert_eq!(&half * &third, Fraction::Small(1, 6));
+        assert_eq!(&half / &third, Fraction::Small(3, 2));
+        assert_eq!(Fraction::from(-7) % Fraction::from(2), Fraction::from(-1));
+    }
+
+    #[test]
+    fn overflow_promotes_to_big() {
+        let max = Fraction::from(i64::MAX);
+        assert_eq!(&max + &Fraction::one(), big(i64::MAX as i128 + 1));
+        assert_eq!(&max * &max, big(i64::MAX as i128 * i64::MAX as i128));
+        let min = Fraction::from(i64::MIN);
+        assert_eq!(&min - &Fraction::one(), big(i64::MIN as i128 - 1));
+        let tiny = Fraction::Small(1, i64::MAX);
+        assert_eq!(
+            &tiny * &tiny,
+            Fraction::Big(BigRational::new(BigInt::one(), BigInt::from(i64::MAX as i128 * i64::MAX as i128)))
+        );
+    }
+
+    #[test]
+    fn results_which_fit_demote_to_small() {
+        let above_max = big(i64::MAX as i128 + 1);
+        assert_eq!(&above_max - &Fraction::one(), Fraction::Small(i64::MAX, 1));
+        let below_min = big(i64::MIN as i128 - 1);
+        assert_eq!(&below_min + &Fraction::one(), Fraction::Small(i64::MIN, 1));
+        assert_eq!(&above_max / &above_max, Fraction::one());
+        assert_eq!(above_max.recip().recip(), above_max);
+    }
+
+    #[test]
+    fn negating_i64_min() {
+        let min = Fraction::from(i64::MIN);
+        assert_eq!(-&min, big(-(i64::MIN as i128)));
+        assert_eq!(-(-&min), min);
+        assert_eq!(min.recip(), Fraction::Big(BigRational::new(BigInt::from(-1), BigInt::from(-(i64::MIN as i128)))));
+        assert_eq!(&Fraction::zero() - &min, big(-(i64::MIN as i128)));
+        assert_eq!(&min / &Fraction::from(-1), big(-(i64::MIN as i128)));
+    }
+
+    #[test]
+    fn values_are_normalised() {
+        assert_eq!(Fraction::new(BigInt::from(2), BigInt::from(4)), Fraction::Small(1, 2));
+        assert_eq!(Fraction::new(BigInt::from(1), BigInt::from(-2)), Fraction::Small(-1, 2));
+        assert_eq!(&Fraction::Small(1, 2) / &Fraction::Small(-1, 3), Fraction::Small(-3, 2));
+        assert_eq!(&Fraction::Small(1, 6) + &Fraction::Small(1, 3), Fraction::Small(1, 2));
+        let huge = BigInt::from(i64::MAX) * BigInt::from(8);
+        assert_eq!(Fraction::new(huge.clone(), huge * BigInt::from(2)), Fraction::Small(1, 2));
+        assert_eq!("6/-4".parse::<Fraction>().unwrap(), Fraction::Small(-3, 2));
+        assert!((&Fraction::Small(1, 3) - &Fraction::Small(1, 3)).is_zero());
+    }
+
+    #[test]
+    fn ordering_across_representations() {
+        let max = Fraction::from(i64::MAX);
+        let above_max = big(i64::MAX as i128 + 1);
+        let below_min = big(i64::MIN as i128 - 1);
+        assert!(max < above_max);
+        assert!(below_min < Fraction::from(i64::MIN));
+        assert!(Fraction::Small(-1, 2) < Fraction::Small(1, 3));
+        assert!(Fraction::Small(i64::MAX - 1, i64::MAX) < Fraction::one());
+        assert_eq!(above_max.cmp(&(&max + &Fraction::one())), Ordering::Equal);
+    }
+
+    #[test]
+    fn equal_values_are_the_same_key() {
+        let mut keys = BTreeMap::new();
+        keys.insert(Fraction::from(i64::MAX), "small");
+        keys.insert(&big(i64::MAX as i128 + 1) - &Fraction::one(), "demoted");
+        keys.insert(Fraction::new(BigInt::from(3), BigInt::from(6)), "half");
+        keys.insert(&Fraction::Small(1, 4) * &Fraction::from(2), "also half");
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[&Fraction::from(i64::MAX)], "demoted");
+        assert_eq!(keys[&Fraction::Small(1, 2)], "also half");
+    }
+}
@@ -2,37 +2,295 @@
 extern crate num_rational;
 extern crate num_bigint;
 
+use std::collections::BTreeMap;
 use std::fmt;
-use std::cell::{RefCell, Ref};
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::io::{self, Write};
 use std::mem::replace;
 use num_traits::cast::ToPrimitive;
 use num_traits::identities::{Zero, One};
+use num_traits::{Pow, Signed};
+use num_bigint::BigInt;
 use std::ops::Index;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
+use crate::profiler::{Position, Profiler};
+use crate::tracer::{self, TraceEntry, Tracer};
 
-pub type Fraction = num_rational::BigRational;
 
-fn fraction_to_f64(x: &Fraction) -> f64 {
-    match (x.numer().to_f64(), x.denom().to_f64()) {
-        (Some(n), Some(d)) => n/d,
-        _ => panic!("Rationals exceed f64 precision")
+pub use crate::fraction::Fraction;
+
+// Powers and left shifts are the operators which can make a huge number out of small ones, so
+// their results are limited to this many bits rather than trying to allocate them
+const MAX_RESULT_BITS: usize = 1 << 24;
+
+// Powers are computed exactly. A fractional exponent p/q takes the q-th root first, which
+// only exists when the numerator and denominator are both perfect q-th powers
+fn exact_pow(base: &Fraction, exponent: &Fraction) -> Result<Fraction, String> {
+    let fail = |reason: &str| Err(format!("({}) ** ({}) {}", base, exponent, reason));
+    let root_degree = match exponent.denom().to_u32() {
+        Some(degree) => degree,
+        None => return fail("needs too large a root")
+    };
+    let power = match exponent.numer().to_i32() {
+        Some(power) => power,
+        None => return fail("has too large an exponent")
+    };
+    if base.is_zero() && power < 0 {
+        return fail("divides by zero");
+    }
+
+    let root = if root_degree == 1 {
+        base.clone()
+    } else {
+        if base.is_negative() && root_degree % 2 == 0 {
+            return fail("has no real value");
+        }
+        let exact_root = |x: &BigInt| {
+            let root = x.abs().nth_root(root_degree);
+            if Pow::pow(&root, root_degree) != x.abs() {None}
+            else if x.is_negative() {Some(-root)}
+            else {Some(root)}
+        };
+        match (exact_root(&base.numer()), exact_root(&base.denom())) {
+            (Some(numer), Some(denom)) => Fraction::new(numer, denom),
+            _ => return fail("has no exact rational value")
+        }
+    };
+
+    // x ** n has at least (bits(x) - 1) * n + 1 bits, so this only turns away results which are
+    // definitely too large, while still letting 1 and -1 be raised to any power
+    let magnitude = power.unsigned_abs();
+    let bits = root.numer().bits().max(root.denom().bits()).saturating_sub(1);
+    if bits.saturating_mul(magnitude as usize) >= MAX_RESULT_BITS {
+        return fail(&format!("would give a number of more than {} bits", MAX_RESULT_BITS));
+    }
+    let result = Fraction::new(Pow::pow(&root.numer(), magnitude), Pow::pow(&root.denom(), magnitude));
+    Ok(if power < 0 {result.recip()} else {result})
+}
+
+fn fraction_to_integer(value: &Fraction, op_symbol: &str) -> Result<BigInt, String> {
+    if !value.is_integer() {
+        return Err(format!("Applying bitwise operator \"{}\" to non-integer {}", op_symbol, value));
+    }
+    Ok(value.to_integer())
+}
+
+fn shift_amount(value: BigInt, op_symbol: &str) -> Result<usize, String> {
+    value.to_usize().ok_or_else(
+        || format!("Shifting by {} using \"{}\", which isn't a valid shift amount", value, op_symbol)
+    )
+}
+
+fn shift_left(value: BigInt, amount: BigInt) -> Result<BigInt, String> {
+    let amount = shift_amount(amount, "<<")?;
+    if value.is_zero() {
+        return Ok(value);
+    }
+    if value.bits().saturating_add(amount) > MAX_RESULT_BITS {
+        return Err(format!(
+            "Shifting {} left by {} would give a number of more than {} bits", value, amount, MAX_RESULT_BITS
+        ));
+    }
+    Ok(value << amount)
+}
+
+// Division by zero is caught here, rather than left to panic inside the fraction arithmetic
+fn fraction_binop(
+    left: &Fraction,
+    right: &Fraction,
+    op_symbol: &str,
+    op: fn(&Fraction, &Fraction) -> Fraction
+) -> Result<Fraction, String> {
+    if right.is_zero() && matches!(op_symbol, "/" | "%" | "//") {
+        return Err(format!("Dividing {} by zero using \"{}\"", left, op_symbol));
+    }
+    Ok(op(left, right))
+}
+
+// Dividing fixed width integers multiplies by the inverse modulo 2^64, which exists for odd
+// numbers, so that "*=" by an odd number can be undone
+fn modular_inverse(value: u64) -> Result<u64, String> {
+    if value.is_multiple_of(2) {
+        return Err(format!(
+            "Dividing fixed width integer by even number {} with \"/\", which multiplies by the inverse and there is none. \
+             Use \"//\" for integer division", value
+        ));
     }
+    // Newton's method, each step doubles the number of correct low bits
+    let mut inverse = value;
+    for _ in 0..5 {
+        inverse = inverse.wrapping_mul(2u64.wrapping_sub(value.wrapping_mul(inverse)));
+    }
+    Ok(inverse)
+}
+
+// Subtracting strings removes a suffix, undoing concatenation
+fn remove_suffix(string: &str, suffix: &str) -> Result<String, String> {
+    match string.strip_suffix(suffix) {
+        Some(stripped) => Ok(stripped.to_string()),
+        None => Err(format!("Subtracting \"{}\" from \"{}\", which doesn't end with it", suffix, string))
+    }
+}
+
+// Applies a binop between equal shape arrays item by item, broadcasting anything else
+fn elementwise(
+    lhs: &Variable,
+    rhs: &Variable,
+    op_name: &str,
+    op: &dyn Fn(&Variable, &Variable) -> Result<Variable, String>
+) -> Result<Variable, String> {
+    let apply = |l: &Variable, r: &Variable| Ok(new_cell(elementwise(l, r, op_name, op)?));
+    Ok(match (lhs, rhs) {
+        (Variable::Array(left), Variable::Array(right)) => {
+            if left.len() != right.len() {
+                return Err(format!("Applying binop \"{}\" to arrays of different shapes", op_name));
+            }
+            Variable::Array(left.iter().zip(right.iter()).map(|(l, r)| apply(&l.borrow(), &r.borrow())).collect::<Result<_, String>>()?)
+        },
+        (Variable::Array(left), right) => {
+            Variable::Array(left.iter().map(|l| apply(&l.borrow(), right)).collect::<Result<_, String>>()?)
+        },
+        (left, Variable::Array(right)) => {
+            Variable::Array(right.iter().map(|r| apply(left, &r.borrow())).collect::<Result<_, String>>()?)
+        },
+        (left, right) => op(left, right)?
+    })
+}
+
+// Stores into arrays item by item, so references to their items stay valid
+fn assign(target: &RefCell<Variable>, value: Variable) {
+    if let (Variable::Array(items), Variable::Array(values)) = (&*target.borrow(), &value) {
+        if items.len() == values.len() {
+            for (item, value) in items.iter().zip(values.iter()) {
+                assign(item, value.borrow().clone());
+            }
+            return;
+        }
+    }
+    *target.borrow_mut() = value;
+}
+
+thread_local! {
+    // Every variable cell is made by new_cell, so the interpreter can tell how many have been
+    // created since it last counted how many are still in use
+    static CELLS_CREATED: Cell<u64> = const { Cell::new(0) };
+}
+
+pub fn new_cell(var: Variable) -> Rc<RefCell<Variable>> {
+    CELLS_CREATED.with(|created| created.set(created.get() + 1));
+    Rc::new(RefCell::new(var))
+}
+
+fn cells_created() -> u64 {
+    CELLS_CREATED.with(Cell::get)
 }
 
 #[derive(PartialEq, Clone)]
 pub enum Variable {
     Frac(Fraction),
     Array(Vec<Rc<RefCell<Variable>>>),
+    Str(String),
+    Func(usize, Rc<str>),  // The function's index, and its name for printing
+    Record(BTreeMap<String, Rc<RefCell<Variable>>>),
+    Map(BTreeMap<MapKey, Rc<RefCell<Variable>>>),
+    Int(FixedInt)
+}
+
+// An unsigned integer of a fixed bit width, whose arithmetic wraps around
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct FixedInt {
+    pub value: u64,
+    pub bits: u32
+}
+
+impl FixedInt {
+    pub fn new(value: u64, bits: u32) -> FixedInt {
+        FixedInt{value: value & FixedInt::mask(bits), bits}
+    }
+
+    fn mask(bits: u32) -> u64 {
+        if bits >= 64 {u64::MAX} else {(1 << bits) - 1}
+    }
+
+    fn from_fraction(value: &Fraction, bits: u32) -> Result<FixedInt, String> {
+        if !value.is_integer() {
+            return Err(format!("Converting non-integer {} to u{}", value, bits));
+        }
+        let modulus = BigInt::one() << bits as usize;
+        let remainder = value.to_integer() % &modulus;
+        let wrapped = if remainder.is_negative() {remainder + modulus} else {remainder};
+        Ok(FixedInt{value: wrapped.to_u64().unwrap(), bits})
+    }
+
+    fn to_fraction(self) -> Fraction {
+        Fraction::from_integer(BigInt::from(self.value))
+    }
+
+    // Integer fractions mixed with fixed width integers are wrapped to the same width
+    fn operands(lhs: &Variable, rhs: &Variable, op_symbol: &str) -> Result<(u64, u64, u32), String> {
+        let bits = match (lhs, rhs) {
+            (Variable::Int(left), Variable::Int(right)) if left.bits != right.bits => {
+                return Err(format!("Applying binop \"{}\" to u{} and u{}", op_symbol, left.bits, right.bits))
+            },
+            (Variable::Int(int), _) | (_, Variable::Int(int)) => int.bits,
+            _ => unreachable!()
+        };
+        let value = |operand: &Variable| match operand {
+            Variable::Int(int) => Ok(int.value),
+            Variable::Frac(frac) => Ok(FixedInt::from_fraction(frac, bits)?.value),
+            _ => Err(format!("Applying binop \"{}\" to incompatible types", op_symbol))
+        };
+        Ok((value(lhs)?, value(rhs)?, bits))
+    }
+
+    fn binop(
+        lhs: &Variable,
+        rhs: &Variable,
+        op_symbol: &str,
+        op: &dyn Fn(u64, u64) -> Result<u64, String>
+    ) -> Result<Variable, String> {
+        let (left, right, bits) = FixedInt::operands(lhs, rhs, op_symbol)?;
+        Ok(Variable::Int(FixedInt::new(op(left, right)?, bits)))
+    }
+
+    // Guards the unsigned integer division operators, which panic when dividing by zero
+    fn nonzero(value: u64, op_symbol: &str) -> Result<u64, String> {
+        if value == 0 {Err(format!("Dividing fixed width integer by zero using \"{}\"", op_symbol))}
+        else          {Ok(value)}
+    }
+}
+
+// Maps are ordered by key, so iterating them is deterministic in both directions
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
+pub enum MapKey {
+    Frac(Fraction),
     Str(String)
 }
 
+impl MapKey {
+    fn to_variable(&self) -> Variable {
+        match self {
+            MapKey::Frac(value) => Variable::Frac(value.clone()),
+            MapKey::Str(value) => Variable::Str(value.clone())
+        }
+    }
+}
+
 impl fmt::Debug for Variable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Variable::Frac(val) => write!(f, "{}", val),
             Variable::Array(vec) => write!(f, "Array({:#?})", vec),
-            Variable::Str(string) => write!(f, "{}", string)
+            Variable::Str(string) => write!(f, "{}", string),
+            Variable::Func(_, name) => write!(f, "Func({})", name),
+            Variable::Record(fields) => write!(f, "Record({:#?})", fields),
+            Variable::Map(entries) => write!(f, "Map({:#?})", entries),
+            Variable::Int(int) => write!(f, "Int(u{}: {})", int.bits, int.value)
         }
     }
 }
@@ -42,43 +300,135 @@ impl fmt::Display for Variable {
         match self {
             Variable::Frac(val) => write!(f, "{}", val),
             Variable::Str(string) => write!(f, "{}", string),
+            Variable::Func(_, name) => write!(f, "<function {}>", name),
+            Variable::Int(int) => write!(f, "{}", int.value),
+            // Items are written straight to the formatter, so a writer which gives up part way
+            // through stops the rest of a large value being formatted
+            Variable::Record(fields) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    write!(f, "{}{}: {}", if i > 0 {", "} else {""}, name, value.borrow())?;
+                }
+                write!(f, "}}")
+            },
+            Variable::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    write!(f, "{}{} => {}", if i > 0 {", "} else {""}, key.to_variable(), value.borrow())?;
+                }
+                write!(f, "}}")
+            },
             Variable::Array(vec) => {
-                let mut out = String::new();
-                if vec.len() > 0 {
-                    for item in vec[0..vec.len() - 1].iter() {
-                        out.push_str(&item.borrow().to_string());
-                        out.push_str(", ");
-                    }
-                    out.push_str(&vec.last().unwrap().borrow().to_string());
+                write!(f, "[")?;
+                for (i, item) in vec.iter().enumerate() {
+                    write!(f, "{}{}", if i > 0 {", "} else {""}, item.borrow())?;
                 }
-                write!(f, "[{}]", out)
+                write!(f, "]")
             }
         }
     }
 }
 
+// Collects formatted text until it's longer than a stack summary can show, then stops the
+// formatting with an error
+struct SummaryWriter {
+    text: String,
+    chars: usize
+}
+
+impl fmt::Write for SummaryWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.chars > tracer::STACK_SUMMARY_CHARS {
+                return Err(fmt::Error);
+            }
+            self.text.push(c);
+            self.chars += 1;
+        }
+        Ok(())
+    }
+}
+
 impl Variable {
     fn to_bool(&self) -> bool {
         match self {
             Variable::Frac(value) => !value.is_zero(),
             Variable::Array(items) => items.len() > 0,
-            Variable::Str(string) => string.len() > 0
+            Variable::Str(string) => string.len() > 0,
+            Variable::Func(..) => true,
+            Variable::Record(fields) => !fields.is_empty(),
+            Variable::Map(entries) => !entries.is_empty(),
+            Variable::Int(int) => int.value != 0
         }
     }
 
-    fn to_usize(&self) -> usize {
+    fn to_usize(&self) -> Result<usize, String> {
         match self {
             Variable::Frac(value) => {
-                value.to_integer().to_usize().unwrap()
+                value.to_usize().ok_or_else(|| format!("Index {} is negative", value))
             },
-            _ => panic!("Index is not a number")
+            Variable::Int(int) => Ok(int.value as usize),
+            _ => Err(String::from("Index is not a number"))
         }
     }
 
-    fn get_array_length(&self) -> usize {
+    fn get_array_length(&self) -> Result<usize, String> {
         match self {
+            Variable::Array(items) => Ok(items.len()),
+            Variable::Map(entries) => Ok(entries.len()),
+            Variable::Str(string) => Ok(string.chars().count()),
+            _ => Err(String::from("Length operator (#) used on non-array"))
+        }
+    }
+
+    // Fixed width integers compare equal to fractions with the same value
+    fn equals(&self, other: &Variable) -> bool {
+        match (self, other) {
+            (Variable::Int(int), Variable::Frac(frac)) | (Variable::Frac(frac), Variable::Int(int)) => {
+                int.to_fraction() == *frac
+            },
+            _ => self == other
+        }
+    }
+
+    // Fixed width integers are the same key as the fraction they're equal to
+    fn to_map_key(&self) -> Result<MapKey, String> {
+        match self {
+            Variable::Frac(value) => Ok(MapKey::Frac(value.clone())),
+            Variable::Int(int) => Ok(MapKey::Frac(int.to_fraction())),
+            Variable::Str(value) => Ok(MapKey::Str(value.clone())),
+            _ => Err(String::from("Map keys must be numbers or strings"))
+        }
+    }
+
+    // A short description for logs, which doesn't print every item of a large collection
+    fn summary(&self) -> String {
+        let size = match self {
             Variable::Array(items) => items.len(),
-            _ => panic!("Length operator (#) used on non-array")
+            Variable::Record(fields) => fields.len(),
+            Variable::Map(entries) => entries.len(),
+            _ => 0
+        };
+        if size > 16 {
+            let kind = match self {Variable::Array(_) => "array", Variable::Record(_) => "record", _ => "map"};
+            return format!("<{} of {} items>", kind, size);
+        }
+        // Only as much is formatted as the trace will show, plus a character to tell it there's more
+        let mut summary = SummaryWriter{text: String::new(), chars: 0};
+        let _ = fmt::Write::write_fmt(&mut summary, format_args!("{}", self));
+        summary.text
+    }
+
+    // The number of cells needed to hold a copy of this variable, not counting its own
+    fn count_inner_cells(&self) -> usize {
+        let count = |items: &mut dyn Iterator<Item=&Rc<RefCell<Variable>>>| {
+            items.map(|item| 1 + item.borrow().count_inner_cells()).sum()
+        };
+        match self {
+            Variable::Array(items) => count(&mut items.iter()),
+            Variable::Record(fields) => count(&mut fields.values()),
+            Variable::Map(entries) => count(&mut entries.values()),
+            _ => 0
         }
     }
 
@@ -86,10 +436,26 @@ impl Variable {
         match self {
             Variable::Frac(value) => Variable::Frac(value.clone()),
             Variable::Str(value) => Variable::Str(value.clone()),
+            Variable::Func(idx, name) => Variable::Func(*idx, Rc::clone(name)),
+            Variable::Int(int) => Variable::Int(*int),
+            Variable::Record(fields) => {
+                Variable::Record(
+                    fields.iter().map(
+                        |(name, value)| (name.clone(), new_cell(value.borrow().deep_copy()))
+                    ).collect()
+                )
+            },
+            Variable::Map(entries) => {
+                Variable::Map(
+                    entries.iter().map(
+                        |(key, value)| (key.clone(), new_cell(value.borrow().deep_copy()))
+                    ).collect()
+                )
+            },
             Variable::Array(items) => {
                 Variable::Array(
                     items.iter().map(
-                        |item| Rc::new(RefCell::new(item.borrow().deep_copy()))
+                        |item| new_cell(item.borrow().deep_copy())
                     ).collect()
                 )
             }
@@ -97,6 +463,37 @@ impl Variable {
     }
 }
 
+impl Variable {
+    // Records are indexed by field name, arrays by position. Characters aren't stored as
+    // variables, so indexing a string gives a new one character string
+    fn subscript(&self, index: &Variable) -> Result<Rc<RefCell<Variable>>, String> {
+        match (self, index) {
+            (Variable::Record(fields), Variable::Str(name)) => {
+                fields.get(name).map(Rc::clone).ok_or_else(|| format!("Record has no field \"{}\"", name))
+            },
+            (Variable::Record(_), _) => Err(String::from("Records can only be indexed by field name")),
+            (Variable::Map(entries), key) => {
+                entries.get(&key.to_map_key()?).map(Rc::clone).ok_or_else(|| format!("Map has no key \"{}\"", key))
+            },
+            (_, Variable::Str(name)) => Err(format!("Looking up field \"{}\" in a non-record", name)),
+            (Variable::Str(string), index) => {
+                let idx = index.to_usize()?;
+                let c = string.chars().nth(idx).ok_or_else(
+                    || format!("String index {} out of range for string of length {}", idx, string.chars().count())
+                )?;
+                Ok(new_cell(Variable::Str(c.to_string())))
+            },
+            (Variable::Array(items), index) => {
+                let idx = index.to_usize()?;
+                items.get(idx).map(Rc::clone).ok_or_else(
+                    || format!("Array index {} out of range for array of length {}", idx, items.len())
+                )
+            },
+            _ => Err(String::from("Indexing is only supported by arrays"))
+        }
+    }
+}
+
 impl Index<usize> for Variable {
     type Output = Rc<RefCell<Variable>>;
 
@@ -112,6 +509,9 @@ impl Index<usize> for Variable {
 struct IterState {
     pub idx: isize,
     pub register: usize,
+    pub index_register: Option<usize>,
+    pub over_keys: bool,
+    pub keys: Vec<MapKey>,  // The keys of a map, which the loop body can't change
     pub var: Rc<RefCell<Variable>>
 }
 
@@ -131,16 +531,21 @@ pub enum Instruction {
     StoreGlobalRegister{register: usize},
     FreeRegister{register: usize},
     Subscript{size: usize},
+    SubscriptRef{size: usize},
     Store,
     Pull{register: usize},
     Push{register: usize},
+    PullIndexed{register: usize},
+    PushIndexed{register: usize},
     CreateInt{val: isize},
     BinopAdd, BinopSub, BinopMul, BinopDiv,
     BinopOr, BinopAnd, BinopXor, 
     BinopLeq, BinopGeq, BinopLess, BinopGreat,
     BinopEq, BinopNeq,
     BinopIDiv, BinopMod, BinopPow,
-    UniopNeg, UniopNot, UniopLen,
+    BinopBitAnd, BinopBitOr, BinopBitXor, BinopShl, BinopShr,
+    UniopNeg, UniopNot, UniopLen, UniopBitNot,
+    ToInt{bits: u32}, ToFrac,
     Reverse{idx: usize},
     Jump{ip: usize},
     JumpIfTrue{ip: usize},
@@ -149,23 +554,99 @@ pub enum Instruction {
     RelativeJumpIfTrue{delta: isize},
     RelativeJumpIfFalse{delta: isize},
     ArrayLiteral{size: usize},
+    RecordLiteral{size: usize},
+    MapLiteral{size: usize},
     ArrayRepeat,
     Call{idx: usize},
     Uncall{idx: usize},
+    CallIndirect,
+    UncallIndirect,
     DuplicateRef,
     UniqueVar,
-    CreateIter{register: usize},
+    CreateIter{register: usize, index_register: Option<usize>, over_keys: bool},
     StepIter{ip: usize},
     Print{count: isize},
+    Assert{line: usize, col: usize},
+    CheckReversible{check: ReversibilityCheck, expected: bool},
+    StatementMarker{idx: usize},
     Quit,
     DebugPrint,
 }
 
 
+// The conditions which reversing a statement relies on, checked at runtime in strict mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReversibilityCheck {
+    IfCondition,  // The other direction's if condition agrees with the branch that ran
+    WhileEntry,   // The other direction's while condition is false on entering the loop
+    WhileRepeat,  // The other direction's while condition is true after each iteration
+    Unlet,        // The variable being removed still holds the value it was created with
+    Delocal       // A local variable holds the value given for the end of its block being left
+}
+
+impl ReversibilityCheck {
+    fn describe(self, expected: bool, forwards: bool) -> String {
+        // Each direction checks the conditions written for the other one
+        let (tilde, run) = if forwards {("~", "running")} else {("", "reversing")};
+        let desc = match self {
+            ReversibilityCheck::IfCondition => format!(
+                "the {}if condition is {} after {} the {} block",
+                tilde, !expected, run, if expected {"if"} else {"else"}),
+            ReversibilityCheck::WhileEntry => format!(
+                "the {}while condition is true on entering the loop", tilde),
+            ReversibilityCheck::WhileRepeat => format!(
+                "the {}while condition is false after {} an iteration", tilde, run),
+            ReversibilityCheck::Unlet => String::from(if forwards {
+                "the variable does not hold the value it is unlet with"
+            } else {
+                "the variable no longer holds the value it was let with"
+            }),
+            ReversibilityCheck::Delocal => String::from(if forwards {
+                "the local variable does not hold its delocal value at the end of the block"
+            } else {
+                "the local variable does not hold its initial value after reversing the block"
+            })
+        };
+        format!("Reversibility check failed: {}", desc)
+    }
+}
+
+
 #[derive(Debug)]
 pub struct Code {
     pub fwd: Vec<Instruction>,
-    pub bkwd: Vec<Instruction>
+    pub bkwd: Vec<Instruction>,
+    // The source (line, col) of the statement each instruction belongs to, where known
+    pub fwd_src_pos: Vec<Option<(usize, usize)>>,
+    pub bkwd_src_pos: Vec<Option<(usize, usize)>>,
+    // Only present when compiled for debugging
+    pub markers: Vec<Marker>,
+    // The (fwd, bkwd) positions of each call statement's call instruction in either stream
+    pub call_links: Vec<(usize, usize)>
+}
+
+// The point just before a statement, with the positions of the marker instructions for it in
+// each stream. Either stream can be missing one, as mono statements have no backward code
+#[derive(Debug)]
+pub struct Marker {
+    pub src_pos: (usize, usize),
+    pub fwd_ip: Option<usize>,
+    pub bkwd_ip: Option<usize>
+}
+
+impl Code {
+    pub fn src_pos(&self, ip: usize, forwards: bool) -> Option<(usize, usize)> {
+        let src_pos = if forwards {&self.fwd_src_pos} else {&self.bkwd_src_pos};
+        src_pos.get(ip).copied().flatten()
+    }
+
+    // The position in the other stream of the call instruction at ip
+    fn linked_call(&self, ip: usize, forwards: bool) -> Option<usize> {
+        self.call_links.iter().find_map(|&(f, b)| {
+            if forwards {Some(b).filter(|_| f == ip)}
+            else        {Some(f).filter(|_| b == ip)}
+        })
+    }
 }
 
 
@@ -174,21 +655,34 @@ pub struct Interpreter<'a> {
     functions: &'a Vec<Function>,
     stack: Vec<StackObject>,
     scope_stack: Vec<Scope<'a>>,
+    global_func_idx: usize,
 
+    func_idx: usize,
     code: &'a Code,
     ip: usize,
     forwards: bool,
     registers: Vec<Option<Rc<RefCell<Variable>>>>,
     global_registers: Vec<Option<Rc<RefCell<Variable>>>>,
-    consts: &'a Vec<Variable>
+    consts: &'a Vec<Variable>,
+    options: &'a Options,
+    instructions_run: u64,
+    next_limit_check: u64,
+    next_cell_count: u64,  // Compared against cells_created
+    next_cell_check: u64,  // Compared against instructions_run
+    cell_count_work: u64,  // Everything looked at by every count of the cells in use
+    profiler: Option<Profiler>,
+    tracer: Option<Tracer>,
+    output: Output
 }
 
 
 #[derive(Debug)]
 pub struct Scope<'a> {
+    func_idx: usize,
     code: &'a Code,
     ip: usize,
     forwards: bool,
+    callee_forwards: bool,  // The direction the function called from here was called in
     registers: Vec<Option<Rc<RefCell<Variable>>>>,
     consts: &'a Vec<Variable>
 }
@@ -196,78 +690,636 @@ pub struct Scope<'a> {
 
 #[derive(Debug)]
 pub struct Function {
+    pub name: String,
+    pub file: String,
     pub code: Code,
     pub consts: Vec<Variable>,
-    pub num_registers: usize
+    pub num_registers: usize,
+    pub symbols: Vec<Symbol>
+}
+
+// A source variable name and the register holding it, between the statements which create and
+// remove it. None means the binding lasts from the start or to the end of the function, which
+// is the case for parameters and returned variables.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub register: usize,
+    pub live_from: Option<(usize, usize)>,
+    pub live_until: Option<(usize, usize)>
+}
+
+impl Symbol {
+    pub fn is_live_at(&self, src_pos: (usize, usize)) -> bool {
+        self.live_from.is_none_or(|from| from <= src_pos) && self.live_until.is_none_or(|until| src_pos <= until)
+    }
+}
+
+
+// Where execute_until offers to pause: just before the statement with the given marker, or
+// when the current function has no more code to run in its current direction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecutionPoint {
+    Statement{marker: usize},
+    EndOfCode
 }
 
 
 #[derive(Debug)]
 pub struct Module {
-    pub main_idx: Option<usize>,
+    pub main_idx: usize,
     pub global_func_idx: usize,
     pub functions: Vec<Function>
 }
 
 
+#[derive(Debug)]
+pub struct Options {
+    pub check_asserts: bool,
+    pub fuel: Option<u64>,  // The most instructions to run, across every call to execute
+    pub deadline: Option<Instant>,
+    pub interrupt: Option<InterruptHandle>,
+    pub max_call_depth: Option<usize>,
+    pub max_stack_size: Option<usize>,
+    pub max_cells: Option<usize>,  // The most variable cells, including array items, in use at once
+    pub profile: bool
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            check_asserts: true,
+            fuel: None,
+            deadline: None,
+            interrupt: None,
+            max_call_depth: None,
+            max_stack_size: None,
+            max_cells: None,
+            profile: false
+        }
+    }
+}
+
+// Lets another thread stop the interpreter, by keeping a clone of the handle given in the options
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn new() -> InterruptHandle {
+        InterruptHandle::default()
+    }
+
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Collects a program's output in memory, for hosts and tests which want to check what it printed.
+// Clones share the same buffer, so one can be given to the interpreter and another kept to read it.
+#[derive(Debug, Clone, Default)]
+pub struct OutputBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl OutputBuffer {
+    pub fn new() -> OutputBuffer {
+        OutputBuffer::default()
+    }
+
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Where print statements write to, which is stdout unless the interpreter is given another
+struct Output(Box<dyn Write>);
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Output")
+    }
+}
+
+// The deadline and interrupt handle are only checked this often, as reading them is slower than
+// most instructions
+const LIMIT_CHECK_INTERVAL: u64 = 4096;
+
+// Counting the cells in use means going through all of them and every register, so it waits until
+// enough have been created that the limit could have been reached, or at least this fraction of
+// it, and until at least as many instructions have run as the last count looked at things. That
+// keeps the cost of counting proportional to the instructions run, however deep the call stack is
+// or however close to the limit, at the price of overshooting the limit by the cells made meanwhile.
+const CELL_RECOUNT_FRACTION: usize = 16;
+
+
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    pub function: String,
+    pub file: String,
+    pub forwards: bool,
+    pub src_pos: Option<(usize, usize)>
+}
+
+// What stopped a program: something going wrong in the program itself, or one of the limits
+// in the options, which stop it cleanly between two instructions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Program,
+    OutOfFuel,
+    DeadlinePassed,
+    Interrupted,
+    CallDepthExceeded,
+    StackSizeExceeded,
+    CellsExceeded
+}
+
+// An error from the instruction loop, before the call stack is attached
+struct Failure {
+    kind: ErrorKind,
+    desc: String
+}
+
+impl From<String> for Failure {
+    fn from(desc: String) -> Failure {
+        Failure{kind: ErrorKind::Program, desc}
+    }
+}
+
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub desc: String,
+    pub function: String,
+    pub file: String,
+    pub forwards: bool,
+    pub src_pos: Option<(usize, usize)>,
+    pub call_stack: Vec<StackFrame>  // Outermost call first, ending with the failing function
+}
+
+
 macro_rules! binop_method {
-    ($name:ident, $op:tt) => {
-        fn $name (&mut self) {
-            let rhs = self.pop_var();
-            let lhs = self.pop_var();
+    ($name:ident, $op:tt $(, $($extra_pattern:pat)|+ => $extra_result:expr)*) => {
+        fn $name (&mut self) -> Result<(), String> {
+            let rhs = self.pop_var()?;
+            let lhs = self.pop_var()?;
+            let op = |lhs: &Variable, rhs: &Variable| -> Result<Variable, String> {
+                Ok(match (lhs, rhs) {
+                    (Variable::Frac(left), Variable::Frac(right)) => {
+                        Variable::Frac(fraction_binop(left, right, stringify!($op), |l, r| l $op r)?)
+                    },
+                    $($($extra_pattern)|+ => {
+                        let result: Result<Variable, String> = $extra_result;
+                        result?
+                    },)*
+                    _ => return Err(format!("Applying binop \"{}\" to incompatible types", stringify!($op)))
+                })
+            };
+            let result = match (&*lhs.borrow(), &*rhs.borrow()) {
+                (Variable::Frac(left), Variable::Frac(right)) => {
+                    Variable::Frac(fraction_binop(left, right, stringify!($op), |l, r| l $op r)?)
+                },
+                (left, right) => elementwise(left, right, stringify!($op), &op)?
+            };
+            self.stack.push(StackObject::Var(new_cell(result)));
+            Ok(())
+        }
+    };
+}
+
+macro_rules! bitwise_method {
+    ($name:ident, $symbol:expr, $op:expr) => {
+        fn $name (&mut self) -> Result<(), String> {
+            let rhs = self.pop_var()?;
+            let lhs = self.pop_var()?;
+            let op: fn(BigInt, BigInt) -> Result<BigInt, String> = $op;
             let result = match (&*lhs.borrow(), &*rhs.borrow()) {
                 (Variable::Frac(left), Variable::Frac(right)) => {
-                    Variable::Frac(left $op right)
+                    let result = op(fraction_to_integer(left, $symbol)?, fraction_to_integer(right, $symbol)?)?;
+                    Variable::Frac(Fraction::from_integer(result))
                 },
-                (Variable::Array(_), Variable::Array(_)) => {
-                    unimplemented!();
+                (left @ Variable::Int(_), right) | (left, right @ Variable::Int(_)) => {
+                    let low_bits = BigInt::from(u64::MAX);
+                    FixedInt::binop(left, right, $symbol, &|l, r| {
+                        Ok((op(BigInt::from(l), BigInt::from(r))? & &low_bits).to_u64().unwrap())
+                    })?
                 },
-                _ => panic!("Applying binop \"{}\" to incompatible types", stringify!($op))
+                _ => return Err(format!("Applying binop \"{}\" to incompatible types", $symbol))
             };
-            self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+            self.stack.push(StackObject::Var(new_cell(result)));
+            Ok(())
+        }
+    };
+}
+
+// The shift amount is a count rather than a value of the same width, so it isn't wrapped.
+// Fixed width values wrap, so shifting them by their width or more leaves nothing, without
+// going through the unbounded shifts that fractions use
+macro_rules! shift_method {
+    ($name:ident, $symbol:expr, $op:expr, $fixed_op:expr) => {
+        fn $name (&mut self) -> Result<(), String> {
+            let rhs = self.pop_var()?;
+            let lhs = self.pop_var()?;
+            let op: fn(BigInt, BigInt) -> Result<BigInt, String> = $op;
+            let fixed_op: fn(u64, u32) -> u64 = $fixed_op;
+            let amount = match &*rhs.borrow() {
+                Variable::Frac(amount) => fraction_to_integer(amount, $symbol)?,
+                Variable::Int(amount) => BigInt::from(amount.value),
+                _ => return Err(format!("Applying binop \"{}\" to incompatible types", $symbol))
+            };
+            let value = match (&*lhs.borrow(), &*rhs.borrow()) {
+                (Variable::Frac(value), Variable::Frac(_)) => {
+                    let result = op(fraction_to_integer(value, $symbol)?, amount)?;
+                    self.stack.push(StackObject::Var(new_cell(Variable::Frac(Fraction::from_integer(result)))));
+                    return Ok(());
+                },
+                (Variable::Int(value), _) => *value,
+                (Variable::Frac(value), Variable::Int(amount)) => FixedInt::from_fraction(value, amount.bits)?,
+                _ => return Err(format!("Applying binop \"{}\" to incompatible types", $symbol))
+            };
+            let amount = if amount.is_negative() {shift_amount(amount, $symbol)?}
+                         else                     {amount.to_usize().unwrap_or(usize::MAX)};
+            let shifted = if amount >= value.bits as usize {0} else {fixed_op(value.value, amount as u32)};
+            self.stack.push(StackObject::Var(new_cell(Variable::Int(FixedInt::new(shifted, value.bits)))));
+            Ok(())
         }
     };
 }
 
 macro_rules! bincomp_method {
     ($name:ident, $op:tt) => {
-        fn $name (&mut self) {
-            let rhs = self.pop_var();
-            let lhs = self.pop_var();
+        fn $name (&mut self) -> Result<(), String> {
+            let rhs = self.pop_var()?;
+            let lhs = self.pop_var()?;
             let result = match (&*lhs.borrow(), &*rhs.borrow()) {
                 (Variable::Frac(left), Variable::Frac(right)) => {
                     if left $op right {Variable::Frac(Fraction::one())}
                     else              {Variable::Frac(Fraction::zero())}
                 },
-                _ => panic!("Applying binop \"{}\" to incompatible types", stringify!($op))
+                (left @ Variable::Int(_), right @ Variable::Int(_)) => {
+                    let (left, right, _) = FixedInt::operands(left, right, stringify!($op))?;
+                    if left $op right {Variable::Frac(Fraction::one())}
+                    else              {Variable::Frac(Fraction::zero())}
+                },
+                // Compared exactly, like equality, rather than wrapping the fraction to the same width
+                (Variable::Int(left), Variable::Frac(right)) => {
+                    if left.to_fraction() $op *right {Variable::Frac(Fraction::one())}
+                    else                             {Variable::Frac(Fraction::zero())}
+                },
+                (Variable::Frac(left), Variable::Int(right)) => {
+                    if *left $op right.to_fraction() {Variable::Frac(Fraction::one())}
+                    else                             {Variable::Frac(Fraction::zero())}
+                },
+                _ => return Err(format!("Applying binop \"{}\" to incompatible types", stringify!($op)))
             };
-            self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+            self.stack.push(StackObject::Var(new_cell(result)));
+            Ok(())
         }
     };
 }
 
 impl<'a> Interpreter<'a> {
 
-    pub fn run(module: &Module) {
-        let main_idx = module.main_idx.expect("No main function");
+    pub fn new(module: &'a Module, options: &'a Options) -> Interpreter<'a> {
         let global_func = module.functions.get(module.global_func_idx).unwrap();
-        let mut interpreter = Interpreter {
+        Interpreter {
             functions: &module.functions,
             stack: Vec::new(),
             scope_stack: Vec::new(),
+            global_func_idx: module.global_func_idx,
+            func_idx: module.global_func_idx,
             code: &global_func.code,
             ip: 0,
             forwards: true,
             registers: Vec::new(),
             global_registers: vec![None; global_func.num_registers],
-            consts: &global_func.consts
+            consts: &global_func.consts,
+            options,
+            instructions_run: 0,
+            next_limit_check: 0,
+            next_cell_count: cells_created(),
+            next_cell_check: 0,
+            cell_count_work: 0,
+            profiler: if options.profile {Some(Profiler::new())} else {None},
+            tracer: None,
+            output: Output(Box::new(io::stdout()))
+        }
+    }
+
+    pub fn run(module: &Module, options: &Options) -> Result<(), Box<RuntimeError>> {
+        Interpreter::new(module, options).run_main(module)
+    }
+
+    // Runs the global scope and then main, leaving the interpreter to be inspected afterwards
+    pub fn run_main(&mut self, module: &Module) -> Result<(), Box<RuntimeError>> {
+        self.execute()?;  // Execute the global scope
+        self.call(module.main_idx, true);  // Initialise call to main
+        self.execute()
+    }
+
+    pub fn execute(&mut self) -> Result<(), Box<RuntimeError>> {
+        self.execute_until(&mut |_, _| false).map(|_| ())
+    }
+
+    // Runs until finished, or until pause returns true at some point, in which case it returns
+    // true and a later call carries on from there
+    pub fn execute_until(
+        &mut self,
+        pause: &mut dyn FnMut(&Interpreter<'a>, ExecutionPoint) -> bool
+    ) -> Result<bool, Box<RuntimeError>> {
+        // Markers do nothing, so resuming from one can step over it without offering to pause again
+        if self.current_marker().is_some() {
+            self.ip += 1;
+        }
+        let mut result = self.execute_instructions(pause);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.pause();
+        }
+        if let (Ok(_), Err(error)) = (&result, self.output.0.flush()) {
+            result = Err(Failure::from(output_error(error)));
+        }
+        if let Some(tracer) = &mut self.tracer {
+            // Flushed whether or not the program failed, since the end of the trace matters most then
+            if let (Ok(_), Err(desc)) = (&result, tracer.flush()) {
+                result = Err(Failure::from(desc));
+            }
+        }
+        result.map_err(|failure| self.runtime_error(failure))
+    }
+
+    // Sends everything printed from here on to out instead of stdout
+    pub fn output_to(&mut self, out: Box<dyn Write>) {
+        self.output = Output(out);
+    }
+
+    // Logs every instruction from here on, or only those in functions with the given name
+    pub fn trace_to(&mut self, out: Box<dyn Write>, function: Option<&str>) -> Result<(), String> {
+        let functions = match function {
+            Some(name) => {
+                let matching: HashSet<_> = self.functions.iter().enumerate()
+                    .filter(|(_, function)| function.name == name)
+                    .map(|(idx, _)| idx)
+                    .collect();
+                if matching.is_empty() {
+                    return Err(format!("There is no function called \"{}\" to trace", name));
+                }
+                Some(matching)
+            },
+            None => None
         };
-        interpreter.execute();  // Execute the global scope
-        interpreter.call(main_idx, true);  // Initialise call to main
-        interpreter.execute();
+        self.tracer = Some(Tracer::new(out, functions));
+        Ok(())
+    }
+
+    fn trace_instruction(&mut self, instruction: &Instruction) -> Result<(), String> {
+        if !self.tracer.as_ref().unwrap().is_tracing(self.func_idx) {
+            return Ok(());
+        }
+        let stack_top = self.stack.iter().rev().take(tracer::STACK_SUMMARY_ITEMS).map(|object| match object {
+            StackObject::Var(var) => var.borrow().summary(),
+            StackObject::Iter(_) => String::from("<iterator>")
+        }).collect();
+        let entry = TraceEntry{
+            function: &self.functions[self.func_idx].name,
+            forwards: self.forwards,
+            ip: self.ip,
+            line: self.code.src_pos(self.ip, self.forwards).map(|(line, _)| line),
+            instruction: format!("{:?}", instruction),
+            stack_size: self.stack.len(),
+            stack_top
+        };
+        self.tracer.as_mut().unwrap().write(entry)
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    fn profile_instruction(&mut self) {
+        let position = Position{
+            func_idx: self.func_idx,
+            forwards: self.forwards,
+            line: self.code.src_pos(self.ip, self.forwards).map(|(line, _)| line),
+            depth: self.scope_stack.len()
+        };
+        let profiler = self.profiler.as_mut().unwrap();
+        if !profiler.is_at(position) {
+            let stack = self.scope_stack.iter()
+                .map(|scope| (scope.func_idx, scope.forwards))
+                .chain(std::iter::once((self.func_idx, self.forwards)))
+                .collect();
+            profiler.move_to(position, stack);
+        }
+        profiler.count_instruction();
+    }
+
+    // Carries on in the other direction from the equivalent point of the other stream, which
+    // exists before each statement with backward code, and once a function without parameters
+    // has run to the end. The function will return into its caller turned around too, so every
+    // caller apart from the outermost must be paused on a call statement
+    pub fn turn_around(&mut self) -> Result<(), String> {
+        let code = self.code;
+        let length = if self.forwards {code.fwd.len()} else {code.bkwd.len()};
+        let ip = if let Some(marker) = self.current_marker() {
+            let ip = if self.forwards {marker.bkwd_ip} else {marker.fwd_ip};
+            ip.ok_or_else(|| String::from("Cannot turn around inside a mono statement"))?
+        } else if self.ip == length {
+            0
+        } else {
+            return Err(String::from("Can only turn around before a statement or at the end of a function"));
+        };
+        // Every caller apart from the global scope has to be able to turn around too
+        let callees = self.scope_stack.iter().skip(1).map(|scope| scope.func_idx).chain(Some(self.func_idx));
+        for (scope, callee) in self.scope_stack.iter().zip(callees).skip(1) {
+            if scope.code.linked_call(scope.ip, scope.forwards).is_none() {
+                return Err(format!(
+                    "Cannot turn around inside {}, which was called from an expression",
+                    self.functions[callee].name));
+            }
+        }
+        self.ip = ip;
+        self.forwards = !self.forwards;
+        Ok(())
     }
 
-    pub fn execute(&mut self) -> () {
+    pub fn depth(&self) -> usize {
+        self.scope_stack.len()
+    }
+
+    pub fn is_forwards(&self) -> bool {
+        self.forwards
+    }
+
+    pub fn current_function(&self) -> &'a Function {
+        &self.functions[self.func_idx]
+    }
+
+    // The marker of the statement about to run, if paused before one
+    pub fn current_marker(&self) -> Option<&'a Marker> {
+        let code = self.code;
+        let instructions = if self.forwards {&code.fwd} else {&code.bkwd};
+        match instructions.get(self.ip) {
+            Some(Instruction::StatementMarker{idx}) => Some(&code.markers[*idx]),
+            _ => None
+        }
+    }
+
+    // Each caller is paused on its call instruction, and the current function where it has got to
+    pub fn call_stack(&self) -> Vec<StackFrame> {
+        self.scope_stack.iter()
+            .map(|scope| (scope.func_idx, scope.code, scope.ip, scope.forwards))
+            .chain(std::iter::once((self.func_idx, self.code, self.ip, self.forwards)))
+            .map(|(idx, code, ip, forwards)| StackFrame{
+                function: self.functions[idx].name.clone(),
+                file: self.functions[idx].file.clone(),
+                forwards,
+                src_pos: code.src_pos(ip, forwards)
+            })
+            .collect()
+    }
+
+    // Finds a variable by its source name, in the current function and then the global scope
+    pub fn lookup_variable(&self, name: &str) -> Option<Rc<RefCell<Variable>>> {
+        self.local_variables().into_iter()
+            .chain(self.global_variables())
+            .find_map(|(var_name, var)| Some(var).filter(|_| var_name == name))
+    }
+
+    // The variables which exist where the current function has got to, in the order they were created
+    pub fn local_variables(&self) -> Vec<(&'a str, Rc<RefCell<Variable>>)> {
+        self.live_variables(self.func_idx, self.src_pos_reached())
+    }
+
+    // The global scope has finished by the time anything else runs, so its variables are the ones
+    // left at the end of it
+    pub fn global_variables(&self) -> Vec<(&'a str, Rc<RefCell<Variable>>)> {
+        if self.func_idx == self.global_func_idx {
+            return Vec::new();
+        }
+        self.live_variables(self.global_func_idx, (usize::MAX, usize::MAX))
+    }
+
+    fn live_variables(&self, func_idx: usize, src_pos: (usize, usize)) -> Vec<(&'a str, Rc<RefCell<Variable>>)> {
+        let functions: &'a Vec<Function> = self.functions;
+        let registers = if func_idx == self.global_func_idx {&self.global_registers} else {&self.registers};
+        functions[func_idx].symbols.iter()
+            .filter(|symbol| symbol.is_live_at(src_pos))
+            .filter_map(|symbol| Some((symbol.name.as_str(), registers[symbol.register].clone()?)))
+            .collect()
+    }
+
+    // The source position the current function has got to. Instructions which aren't part of a
+    // statement, like those passing parameters, take the position of the last statement before
+    // them, or come before every statement in the direction of travel if there isn't one.
+    fn src_pos_reached(&self) -> (usize, usize) {
+        (0..=self.ip).rev()
+            .find_map(|ip| self.code.src_pos(ip, self.forwards))
+            .unwrap_or(if self.forwards {(0, 0)} else {(usize::MAX, usize::MAX)})
+    }
+
+    // Registers should always hold a value when read, so an empty one is named in the error to
+    // help track down how it happened
+    fn empty_register_error(&self, func_idx: usize, register: usize) -> String {
+        let src_pos = if func_idx == self.func_idx {self.src_pos_reached()} else {(usize::MAX, usize::MAX)};
+        let symbol = self.functions[func_idx].symbols.iter()
+            .find(|symbol| symbol.register == register && symbol.is_live_at(src_pos));
+        match symbol {
+            Some(symbol) => format!("Reading variable \"{}\" which holds no value", symbol.name),
+            None => format!("Reading register {} which holds no value", register)
+        }
+    }
+
+    // The interpreter state is left where the error happened, so the call stack can be read off it.
+    // Boxed so that the Result of running stays small
+    fn runtime_error(&self, Failure{kind, desc}: Failure) -> Box<RuntimeError> {
+        let call_stack = self.call_stack();
+        let StackFrame{function, file, forwards, src_pos} = call_stack.last().unwrap().clone();
+        Box::new(RuntimeError{kind, desc, function, file, forwards, src_pos, call_stack})
+    }
+
+    pub fn instructions_run(&self) -> u64 {
+        self.instructions_run
+    }
+
+    // Stops before running any more instructions if one of the limits has been reached
+    fn check_limits(&mut self) -> Result<(), Failure> {
+        let options = self.options;
+        if let Some(fuel) = options.fuel.filter(|&fuel| self.instructions_run >= fuel) {
+            return Err(Failure{kind: ErrorKind::OutOfFuel, desc: format!("Ran out of fuel after {} instructions", fuel)});
+        }
+        if options.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(Failure{kind: ErrorKind::DeadlinePassed, desc: String::from("Passed the deadline")});
+        }
+        if options.interrupt.as_ref().is_some_and(InterruptHandle::is_interrupted) {
+            return Err(Failure{kind: ErrorKind::Interrupted, desc: String::from("Interrupted")});
+        }
+        self.next_limit_check = self.instructions_run + LIMIT_CHECK_INTERVAL;
+        if let Some(fuel) = options.fuel {
+            self.next_limit_check = self.next_limit_check.min(fuel);
+        }
+        Ok(())
+    }
+
+    fn check_cells(&mut self) -> Result<(), Failure> {
+        let max = self.options.max_cells.unwrap();
+        let (live, looked_at) = self.count_live_cells();
+        if live > max {
+            return Err(Failure{kind: ErrorKind::CellsExceeded, desc: format!(
+                "Using {} variable cells, more than the limit of {}", live, max)});
+        }
+        let headroom = (max - live).max(max / CELL_RECOUNT_FRACTION).max(1);
+        self.next_cell_count = cells_created() + headroom as u64;
+        self.next_cell_check = self.instructions_run + looked_at as u64;
+        self.cell_count_work += looked_at as u64;
+        Ok(())
+    }
+
+    // Cells can be shared by several references, so each is only counted the first time it's found.
+    // Also gives how many registers, stack items and cells were looked at to count them
+    fn count_live_cells(&self) -> (usize, usize) {
+        let registers = self.scope_stack.iter()
+            .flat_map(|scope| scope.registers.iter())
+            .chain(self.registers.iter())
+            .chain(self.global_registers.iter())
+            .flatten();
+        let num_registers = self.scope_stack.iter().map(|scope| scope.registers.len()).sum::<usize>()
+            + self.registers.len() + self.global_registers.len();
+        let stack = self.stack.iter().map(|object| match object {
+            StackObject::Var(var) => var,
+            StackObject::Iter(IterState{var, ..}) => var
+        });
+        let mut to_visit: Vec<Rc<RefCell<Variable>>> = registers.chain(stack).cloned().collect();
+        let mut seen = HashSet::new();
+        while let Some(cell) = to_visit.pop() {
+            if !seen.insert(Rc::as_ptr(&cell)) {
+                continue;
+            }
+            match &*cell.borrow() {
+                Variable::Array(items) => to_visit.extend(items.iter().cloned()),
+                Variable::Record(fields) => to_visit.extend(fields.values().cloned()),
+                Variable::Map(entries) => to_visit.extend(entries.values().cloned()),
+                _ => {}
+            }
+        }
+        (seen.len(), num_registers + self.stack.len() + seen.len())
+    }
+
+    fn execute_instructions(
+        &mut self,
+        pause: &mut dyn FnMut(&Interpreter<'a>, ExecutionPoint) -> bool
+    ) -> Result<bool, Failure> {
 
         'refresh_instructions: loop{
 
@@ -279,62 +1331,107 @@ impl<'a> Interpreter<'a> {
                 let instruction = match instructions.get(self.ip) {
                     Some(inst) => inst,
                     None => {
+                        if pause(self, ExecutionPoint::EndOfCode) {
+                            return Ok(true);
+                        }
                         if self.scope_stack.is_empty() { 
                             break 'refresh_instructions;
                         } else {
-                            self.end_call(); 
+                            self.end_call()?; 
                             continue 'refresh_instructions;
                         };
                     }
                 };
 
-                // println!("{} IP: {}, {:?}", if self.forwards {"FWD"} else {"BKWD"}, self.ip, instruction);
+                if self.instructions_run == self.next_limit_check {
+                    self.check_limits()?;
+                }
+                self.instructions_run += 1;
+                if self.profiler.is_some() {
+                    self.profile_instruction();
+                }
+                if self.tracer.is_some() {
+                    self.trace_instruction(instruction)?;
+                }
+                if let Some(max) = self.options.max_stack_size.filter(|&max| self.stack.len() > max) {
+                    return Err(Failure{kind: ErrorKind::StackSizeExceeded, desc: format!(
+                        "The operand stack grew past {} items", max)});
+                }
+                if self.options.max_cells.is_some()
+                    && cells_created() >= self.next_cell_count
+                    && self.instructions_run >= self.next_cell_check {
+                    self.check_cells()?;
+                }
 
                 match instruction {
                     Instruction::LoadConst{idx} => self.load_const(*idx),
-                    Instruction::LoadRegister{register} => self.load_register(*register),
-                    Instruction::LoadGlobalRegister{register} => self.load_global_register(*register),
-                    Instruction::StoreRegister{register} => self.store_register(*register),
-                    Instruction::StoreGlobalRegister{register} => self.store_global_register(*register),
+                    Instruction::LoadRegister{register} => self.load_register(*register)?,
+                    Instruction::LoadGlobalRegister{register} => self.load_global_register(*register)?,
+                    Instruction::StoreRegister{register} => self.store_register(*register)?,
+                    Instruction::StoreGlobalRegister{register} => self.store_global_register(*register)?,
                     Instruction::FreeRegister{register} => self.free_register(*register),
-                    Instruction::Store => self.store(),
-                    Instruction::Subscript{size} => self.subscript(*size),
-                    Instruction::DuplicateRef => self.duplicate_ref(),
-                    Instruction::UniqueVar => self.copy_var(),
+                    Instruction::Store => self.store()?,
+                    Instruction::Subscript{size} => self.subscript(*size, false)?,
+                    Instruction::SubscriptRef{size} => self.subscript(*size, true)?,
+                    Instruction::DuplicateRef => self.duplicate_ref()?,
+                    Instruction::UniqueVar => self.copy_var()?,
                     Instruction::CreateInt{val} => self.create_int(*val),
-                    Instruction::BinopAdd => self.binop_add(),
-                    Instruction::BinopSub => self.binop_sub(),
-                    Instruction::BinopMul => self.binop_mul(),
-                    Instruction::BinopDiv => self.binop_div(),
-                    Instruction::BinopMod => self.binop_mod(),
-                    Instruction::BinopIDiv => self.binop_idiv(),
-                    Instruction::BinopPow => self.binop_pow(),
-                    Instruction::BinopLess => self.binop_less(),
-                    Instruction::BinopLeq => self.binop_leq(),
-                    Instruction::BinopGreat => self.binop_great(),
-                    Instruction::BinopGeq => self.binop_geq(),
-                    Instruction::BinopEq => self.binop_eq(),
-                    Instruction::BinopNeq => self.binop_neq(),
-                    Instruction::BinopXor => self.binop_xor(),
-                    Instruction::UniopNeg => self.uniop_neg(),
-                    Instruction::UniopNot => self.uniop_not(),
-                    Instruction::UniopLen => self.uniop_len(),
-                    Instruction::ArrayLiteral{size} => self.array_literal(*size),
-                    Instruction::ArrayRepeat => self.array_repeat(),
-                    Instruction::Pull{register} => self.pull(*register),
-                    Instruction::Push{register} => self.push(*register),
-                    Instruction::Print{count} => self.print(*count),
-                    Instruction::CreateIter{register} => self.create_iter(*register),
-                    Instruction::StepIter{ip} => {self.step_iter(*ip); continue 'refresh_instructions},
+                    Instruction::BinopAdd => self.binop_add()?,
+                    Instruction::BinopSub => self.binop_sub()?,
+                    Instruction::BinopMul => self.binop_mul()?,
+                    Instruction::BinopDiv => self.binop_div()?,
+                    Instruction::BinopMod => self.binop_mod()?,
+                    Instruction::BinopIDiv => self.binop_idiv()?,
+                    Instruction::BinopPow => self.binop_pow()?,
+                    Instruction::BinopLess => self.binop_less()?,
+                    Instruction::BinopLeq => self.binop_leq()?,
+                    Instruction::BinopGreat => self.binop_great()?,
+                    Instruction::BinopGeq => self.binop_geq()?,
+                    Instruction::BinopEq => self.binop_eq()?,
+                    Instruction::BinopNeq => self.binop_neq()?,
+                    Instruction::BinopXor => self.binop_xor()?,
+                    Instruction::BinopBitAnd => self.binop_bitand()?,
+                    Instruction::BinopBitOr => self.binop_bitor()?,
+                    Instruction::BinopBitXor => self.binop_bitxor()?,
+                    Instruction::BinopShl => self.binop_shl()?,
+                    Instruction::BinopShr => self.binop_shr()?,
+                    Instruction::UniopBitNot => self.uniop_bitnot()?,
+                    Instruction::ToInt{bits} => self.cast_int(*bits)?,
+                    Instruction::ToFrac => self.cast_frac()?,
+                    Instruction::UniopNeg => self.uniop_neg()?,
+                    Instruction::UniopNot => self.uniop_not()?,
+                    Instruction::UniopLen => self.uniop_len()?,
+                    Instruction::ArrayLiteral{size} => self.array_literal(*size)?,
+                    Instruction::RecordLiteral{size} => self.record_literal(*size)?,
+                    Instruction::MapLiteral{size} => self.map_literal(*size)?,
+                    Instruction::ArrayRepeat => self.array_repeat()?,
+                    Instruction::Pull{register} => self.pull(*register)?,
+                    Instruction::Push{register} => self.push(*register)?,
+                    Instruction::PullIndexed{register} => self.pull_indexed(*register)?,
+                    Instruction::PushIndexed{register} => self.push_indexed(*register)?,
+                    Instruction::Print{count} => self.print(*count)?,
+                    Instruction::Assert{line, col} => self.assert(*line, *col)?,
+                    Instruction::CheckReversible{check, expected} => self.check_reversible(*check, *expected)?,
+                    Instruction::CreateIter{register, index_register, over_keys} => {
+                        self.create_iter(*register, *index_register, *over_keys)?
+                    },
+                    Instruction::StepIter{ip} => {self.step_iter(*ip)?; continue 'refresh_instructions},
                     
                     Instruction::Jump{ip} => {self.jump(*ip); continue 'refresh_instructions},
-                    Instruction::JumpIfTrue{ip} => {self.jump_if_true(*ip); continue 'refresh_instructions},
-                    Instruction::JumpIfFalse{ip} => {self.jump_if_false(*ip); continue 'refresh_instructions},
-                    Instruction::Call{idx} => {self.call(*idx, true); continue 'refresh_instructions},
-                    Instruction::Uncall{idx} => {self.call(*idx, false); continue 'refresh_instructions},
+                    Instruction::JumpIfTrue{ip} => {self.jump_if_true(*ip)?; continue 'refresh_instructions},
+                    Instruction::JumpIfFalse{ip} => {self.jump_if_false(*ip)?; continue 'refresh_instructions},
+                    Instruction::Call{idx} => {self.checked_call(*idx, true)?; continue 'refresh_instructions},
+                    Instruction::Uncall{idx} => {self.checked_call(*idx, false)?; continue 'refresh_instructions},
+                    Instruction::CallIndirect => {self.call_indirect(true)?; continue 'refresh_instructions},
+                    Instruction::UncallIndirect => {self.call_indirect(false)?; continue 'refresh_instructions},
                     Instruction::Reverse{idx} => {self.reverse(*idx); continue 'refresh_instructions;}
+                    Instruction::StatementMarker{idx} => {
+                        if pause(self, ExecutionPoint::Statement{marker: *idx}) {
+                            return Ok(true);
+                        }
+                    },
                     Instruction::Quit => break 'refresh_instructions,
-                    Instruction::DebugPrint => self.debug_print(),
+                    Instruction::DebugPrint => self.debug_print()?,
 
                     
                     Instruction::BinopAnd => unimplemented!("BinopAnd"),
@@ -347,28 +1444,61 @@ impl<'a> Interpreter<'a> {
                 self.ip += 1;
             }
         }
+        Ok(false)
     }
 
     pub fn call(&mut self, func_idx: usize, forwards: bool) {
         let func: &'a Function = self.functions.get(func_idx).expect("Call to undefined function");
         self.scope_stack.push(
             Scope{
+                func_idx  : replace(&mut self.func_idx , func_idx),
                 code      : replace(&mut self.code     , &func.code),
                 consts    : replace(&mut self.consts   , &func.consts),
                 registers : replace(&mut self.registers, vec![None; func.num_registers]),
                 ip        : replace(&mut self.ip       , 0),
-                forwards  : replace(&mut self.forwards , forwards)
+                forwards  : replace(&mut self.forwards , forwards),
+                callee_forwards: forwards
             }
         );
     }
 
-    pub fn end_call(&mut self) {
+    // Calls made by the program, rather than the one into main, count towards the call depth limit
+    fn checked_call(&mut self, func_idx: usize, forwards: bool) -> Result<(), Failure> {
+        if let Some(max) = self.options.max_call_depth.filter(|&max| self.scope_stack.len() >= max) {
+            return Err(Failure{kind: ErrorKind::CallDepthExceeded, desc: format!(
+                "Calling {} would go more than {} calls deep", self.functions[func_idx].name, max)});
+        }
+        self.call(func_idx, forwards);
+        Ok(())
+    }
+
+    fn call_indirect(&mut self, forwards: bool) -> Result<(), Failure> {
+        let func_idx = match &*self.pop_var()?.borrow() {
+            Variable::Func(idx, _) => *idx,
+            _ => return Err(Failure::from(String::from("Calling a variable which doesn't hold a function")))
+        };
+        self.checked_call(func_idx, forwards)
+    }
+
+    pub fn end_call(&mut self) -> Result<(), String> {
+        // A function which has been turned around returns into its caller turned around too,
+        // carrying on from the other stream's instruction for the same call
+        let scope = self.scope_stack.last().unwrap();
+        let (ip, forwards) = if self.forwards == scope.callee_forwards {
+            (scope.ip, scope.forwards)
+        } else {
+            let ip = scope.code.linked_call(scope.ip, scope.forwards).ok_or_else(|| format!(
+                "Cannot return into {} turned around", self.functions[scope.func_idx].name))?;
+            (ip, !scope.forwards)
+        };
         let scope = self.scope_stack.pop().unwrap();
+        self.func_idx = scope.func_idx;
         self.code = scope.code;
         self.consts = scope.consts;
         self.registers = scope.registers;
-        self.ip = scope.ip + 1;
-        self.forwards = scope.forwards;
+        self.ip = ip + 1;
+        self.forwards = forwards;
+        Ok(())
     }
 
     #[inline]
@@ -377,21 +1507,23 @@ impl<'a> Interpreter<'a> {
     }
 
     #[inline]
-    fn jump_if_true(&mut self, ip: usize) {
-        if self.pop_var().borrow().to_bool() {
+    fn jump_if_true(&mut self, ip: usize) -> Result<(), String> {
+        if self.pop_var()?.borrow().to_bool() {
             self.jump(ip);
         } else {
             self.ip += 1;
         }
+        Ok(())
     }
 
     #[inline]
-    fn jump_if_false(&mut self, ip: usize) {
-        if !self.pop_var().borrow().to_bool() {
+    fn jump_if_false(&mut self, ip: usize) -> Result<(), String> {
+        if !self.pop_var()?.borrow().to_bool() {
             self.jump(ip);
         } else {
             self.ip += 1;
         }
+        Ok(())
     }
 
     #[inline]
@@ -402,31 +1534,41 @@ impl<'a> Interpreter<'a> {
 
     #[inline]
     fn load_const(&mut self, idx: usize) {
-        self.stack.push(StackObject::Var(Rc::new(RefCell::new(
+        self.stack.push(StackObject::Var(new_cell(
             self.consts[idx].clone()
-        ))));
+        )));
     }  
 
     #[inline]
-    fn load_register(&mut self, idx: usize) {
-        let new_var_ref = Rc::clone(self.registers[idx].as_ref().unwrap());
+    fn load_register(&mut self, idx: usize) -> Result<(), String> {
+        let new_var_ref = match &self.registers[idx] {
+            Some(var) => Rc::clone(var),
+            None => return Err(self.empty_register_error(self.func_idx, idx))
+        };
         self.stack.push(StackObject::Var(new_var_ref));
+        Ok(())
     }
 
     #[inline]
-    fn load_global_register(&mut self, idx: usize) {
-        let new_var_ref = Rc::clone(self.global_registers[idx].as_ref().unwrap());
+    fn load_global_register(&mut self, idx: usize) -> Result<(), String> {
+        let new_var_ref = match &self.global_registers[idx] {
+            Some(var) => Rc::clone(var),
+            None => return Err(self.empty_register_error(self.global_func_idx, idx))
+        };
         self.stack.push(StackObject::Var(new_var_ref));
+        Ok(())
     }
 
     #[inline]
-    fn store_register(&mut self, idx: usize) {
-        self.registers[idx] = Some(self.pop_var());
+    fn store_register(&mut self, idx: usize) -> Result<(), String> {
+        self.registers[idx] = Some(self.pop_var()?);
+        Ok(())
     }
 
     #[inline]
-    fn store_global_register(&mut self, idx: usize) {
-        self.global_registers[idx] = Some(self.pop_var());
+    fn store_global_register(&mut self, idx: usize) -> Result<(), String> {
+        self.global_registers[idx] = Some(self.pop_var()?);
+        Ok(())
     }
 
     #[inline]
@@ -434,282 +1576,666 @@ impl<'a> Interpreter<'a> {
         self.registers[idx] = None;
     }
 
-    pub fn array_literal(&mut self, size: usize) {
+    pub fn array_literal(&mut self, size: usize) -> Result<(), String> {
         let mut items = Vec::with_capacity(size);
         for _ in 0..size {
-            let mut item = self.pop_var();
+            let mut item = self.pop_var()?;
             if Rc::strong_count(&item) > 1 {
                 let val = item.borrow().deep_copy();
-                item = Rc::new(RefCell::new(val));
+                item = new_cell(val);
             };
             items.push(item);
         }
-        self.stack.push(StackObject::Var(Rc::new(RefCell::new(
+        self.stack.push(StackObject::Var(new_cell(
             Variable::Array(items)
-        ))));
+        )));
+        Ok(())
+    }
+
+    pub fn record_literal(&mut self, size: usize) -> Result<(), String> {
+        let mut fields = BTreeMap::new();
+        for _ in 0..size {
+            let name = match &*self.pop_var()?.borrow() {
+                Variable::Str(name) => name.clone(),
+                _ => return Err(String::from("Record field names must be strings"))
+            };
+            let mut value = self.pop_var()?;
+            if Rc::strong_count(&value) > 1 {
+                let val = value.borrow().deep_copy();
+                value = new_cell(val);
+            };
+            fields.insert(name, value);
+        }
+        self.stack.push(StackObject::Var(new_cell(
+            Variable::Record(fields)
+        )));
+        Ok(())
     }
 
-    pub fn array_repeat(&mut self) {
+    pub fn map_literal(&mut self, size: usize) -> Result<(), String> {
+        let mut entries = BTreeMap::new();
+        for _ in 0..size {
+            let key = self.pop_var()?.borrow().to_map_key()?;
+            let mut value = self.pop_var()?;
+            if Rc::strong_count(&value) > 1 {
+                let val = value.borrow().deep_copy();
+                value = new_cell(val);
+            };
+            if entries.insert(key, value).is_some() {
+                return Err(String::from("Duplicate key in map literal"));
+            }
+        }
+        self.stack.push(StackObject::Var(new_cell(
+            Variable::Map(entries)
+        )));
+        Ok(())
+    }
+
+    fn array_repeat(&mut self) -> Result<(), Failure> {
 
-        let dimensions = self.pop_var();
-        let content = self.pop_var();
+        let dimensions = self.pop_var()?;
+        let content = self.pop_var()?;
 
         let dimensions: Vec<_> = match &*dimensions.borrow() {
-            Variable::Array(array) => array.iter().map(|d| d.borrow().to_usize()).collect(),
-            Variable::Frac(value) => vec![value.to_integer().to_usize().unwrap()],
-            Variable::Str(_) => panic!("Array repetition dimensions must be specified in an array")
+            Variable::Array(array) => array.iter().map(|d| d.borrow().to_usize()).collect::<Result<_, String>>()?,
+            Variable::Frac(_) => vec![dimensions.borrow().to_usize()?],
+            _ => return Err(Failure::from(String::from("Array repetition dimensions must be specified in an array")))
         };
+
+        // A single repetition can ask for more than fits in memory, so it's checked before allocating
+        if let Some(max) = self.options.max_cells {
+            let mut arrays_at_depth = 1usize;
+            let mut cells = Some(1usize);
+            for &dimension in &dimensions {
+                arrays_at_depth = arrays_at_depth.saturating_mul(dimension);
+                cells = cells.and_then(|cells| cells.checked_add(arrays_at_depth));
+            }
+            let content_cells = arrays_at_depth.checked_mul(content.borrow().count_inner_cells());
+            let total = cells.zip(content_cells).and_then(|(cells, content_cells)| cells.checked_add(content_cells));
+            if total.is_none_or(|total| total > max) {
+                return Err(Failure{kind: ErrorKind::CellsExceeded, desc: format!(
+                    "Repeating an array would make more than the limit of {} variable cells", max)});
+            }
+        }
         
         fn recursive_array_maker(content: &Variable, dims: &[usize]) -> Vec<Rc<RefCell<Variable>>> {
             let mut ret = Vec::with_capacity(dims[0]);
             if dims.len() == 1 {
                 for _ in 0..dims[0] {
-                    ret.push(Rc::new(RefCell::new(content.deep_copy())));
+                    ret.push(new_cell(content.deep_copy()));
                 }
             } else {
                 for _ in 0..dims[0] {
-                    ret.push(Rc::new(RefCell::new(Variable::Array(
+                    ret.push(new_cell(Variable::Array(
                         recursive_array_maker(content, &dims[1..])
-                    ))));
+                    )));
                 }
             }
             ret
         }
 
         let array = recursive_array_maker(&*content.borrow(), dimensions.as_slice());
-        let var = Rc::new(RefCell::new(Variable::Array(array)));
+        let var = new_cell(Variable::Array(array));
         self.stack.push(StackObject::Var(var));
+        Ok(())
     }
 
-    fn subscript(&mut self, size: usize) {
-        let mut var_ref = self.pop_var();
+    // Indexing a string gives a new string, so anything modified or referenced through it
+    // would never reach the original
+    fn subscript(&mut self, size: usize, is_ref: bool) -> Result<(), String> {
+        let mut var_ref = self.pop_var()?;
         for _ in 0..size {
-            let index = self.pop_var().borrow().to_usize();
-            let new_ref = Rc::clone(&Ref::map(var_ref.borrow(), |var| &var[index]));
+            if is_ref && matches!(*var_ref.borrow(), Variable::Str(_)) {
+                return Err(String::from("The characters of a string can be read, but not modified or referenced"));
+            }
+            let index = self.pop_var()?;
+            let new_ref = var_ref.borrow().subscript(&index.borrow())?;
             var_ref = new_ref;
         }
         self.stack.push(StackObject::Var(var_ref));
+        Ok(())
     }
 
-    fn store(&mut self) {
-        let value = self.pop_var().borrow().clone();
-        *self.pop_var().borrow_mut() = value;
+    fn store(&mut self) -> Result<(), String> {
+        let value = self.pop_var()?.borrow().clone();
+        assign(&*self.pop_var()?, value);
+        Ok(())
     }
 
-    fn duplicate_ref(&mut self) {
+    fn duplicate_ref(&mut self) -> Result<(), String> {
         let new = match self.stack.last().unwrap() {
             StackObject::Var(cell) => StackObject::Var(Rc::clone(cell)),
-            _ => panic!("Trying to duplicate non-variable")
+            _ => return Err(String::from("Trying to duplicate non-variable"))
         };
         self.stack.push(new);
+        Ok(())
     }
 
-    fn copy_var(&mut self) {
-        let var = self.pop_var();
+    fn copy_var(&mut self) -> Result<(), String> {
+        let var = self.pop_var()?;
         if Rc::strong_count(&var) > 1 {
             self.stack.push(
-                StackObject::Var(Rc::new(RefCell::new(
+                StackObject::Var(new_cell(
                     var.borrow().deep_copy()
                 ))
-            ));
+            );
         } else {
             self.stack.push(StackObject::Var(var));
         }
+        Ok(())
     }
 
     fn create_int(&mut self, val: isize) {
-        self.stack.push(StackObject::Var(Rc::new(RefCell::new(
-            Variable::Frac(Fraction::from_integer(
-                num_bigint::BigInt::from(val)
-            ))
-        ))));
+        self.stack.push(StackObject::Var(new_cell(
+            Variable::Frac(Fraction::from(val as i64))
+        )));
     }
 
-    binop_method!(binop_add, +);
-    binop_method!(binop_sub, -);
-    binop_method!(binop_mul, *);
-    binop_method!(binop_div, /);
-    binop_method!(binop_mod, %);
+    binop_method!(binop_add, +,
+        (Variable::Str(left), Variable::Str(right)) => Ok(Variable::Str(format!("{}{}", left, right))),
+        (left @ Variable::Int(_), right) | (left, right @ Variable::Int(_)) => FixedInt::binop(left, right, "+", &|l, r| Ok(l.wrapping_add(r)))
+    );
+    binop_method!(binop_sub, -,
+        (Variable::Str(left), Variable::Str(right)) => remove_suffix(left, right).map(Variable::Str),
+        (left @ Variable::Int(_), right) | (left, right @ Variable::Int(_)) => FixedInt::binop(left, right, "-", &|l, r| Ok(l.wrapping_sub(r)))
+    );
+    binop_method!(binop_mul, *,
+        (left @ Variable::Int(_), right) | (left, right @ Variable::Int(_)) => FixedInt::binop(left, right, "*", &|l, r| Ok(l.wrapping_mul(r)))
+    );
+    binop_method!(binop_div, /,
+        (left @ Variable::Int(_), right) | (left, right @ Variable::Int(_)) => FixedInt::binop(left, right, "/", &|l, r| {
+            Ok(l.wrapping_mul(modular_inverse(r)?))
+        })
+    );
+    binop_method!(binop_mod, %,
+        (left @ Variable::Int(_), right) | (left, right @ Variable::Int(_)) => FixedInt::binop(left, right, "%", &|l, r| Ok(l % FixedInt::nonzero(r, "%")?))
+    );
 
     bincomp_method!(binop_less,  < );
     bincomp_method!(binop_leq,   <=);
     bincomp_method!(binop_great, > );
     bincomp_method!(binop_geq,   >=);
     
-    fn binop_xor (&mut self) {
-        let rhs = self.pop_var();
-        let lhs = self.pop_var();
+    fn binop_xor (&mut self) -> Result<(), String> {
+        let rhs = self.pop_var()?;
+        let lhs = self.pop_var()?;
         let result = lhs.borrow().to_bool() ^ rhs.borrow().to_bool();
         self.create_int(if result {1} else {0});
+        Ok(())
     }
 
-    fn binop_idiv(&mut self) {
-        let rhs = self.pop_var();
-        let lhs = self.pop_var();
+    bitwise_method!(binop_bitand, ".&", |l, r| Ok(l & r));
+    bitwise_method!(binop_bitor,  ".|", |l, r| Ok(l | r));
+    bitwise_method!(binop_bitxor, ".^", |l, r| Ok(l ^ r));
+    shift_method!(binop_shl, "<<", shift_left, |value, amount| value << amount);
+    shift_method!(binop_shr, ">>", |l, r| Ok(l >> shift_amount(r, ">>")?), |value, amount| value >> amount);
+
+    fn binop_idiv(&mut self) -> Result<(), String> {
+        let rhs = self.pop_var()?;
+        let lhs = self.pop_var()?;
         let result = match (&*lhs.borrow(), &*rhs.borrow()) {
             (Variable::Frac(left), Variable::Frac(right)) => {
-                Variable::Frac((left/right).trunc())
+                Variable::Frac(fraction_binop(left, right, "//", |l, r| (l / r).trunc())?)
             },
-            _ => panic!("Applying binop \"//\" to incompatible types")
+            (left @ Variable::Int(_), right) | (left, right @ Variable::Int(_)) => {
+                FixedInt::binop(left, right, "//", &|l, r| Ok(l / FixedInt::nonzero(r, "//")?))?
+            },
+            _ => return Err(String::from("Applying binop \"//\" to incompatible types"))
         };
-        self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+        self.stack.push(StackObject::Var(new_cell(result)));
+        Ok(())
     }
 
-    fn binop_pow(&mut self) {
-        let rhs = self.pop_var();
-        let lhs = self.pop_var();
+    fn binop_pow(&mut self) -> Result<(), String> {
+        let rhs = self.pop_var()?;
+        let lhs = self.pop_var()?;
         let result = match (&*lhs.borrow(), &*rhs.borrow()) {
             (Variable::Frac(left), Variable::Frac(right)) => {
-                let value = fraction_to_f64(left).powf(fraction_to_f64(right));
-                let value = Fraction::from_float(value).expect("Computing power created an infinite float");
-                Variable::Frac(value)
+                Variable::Frac(exact_pow(left, right)?)
             },
-            _ => panic!("Applying binop \"**\" to incompatible types")
+            _ => return Err(String::from("Applying binop \"**\" to incompatible types"))
         };
-        self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+        self.stack.push(StackObject::Var(new_cell(result)));
+        Ok(())
     }
 
-    fn binop_eq(&mut self) {
-        let rhs = self.pop_var();
-        let lhs = self.pop_var();
-        let value = if *lhs.borrow() == *rhs.borrow() {Fraction::one()}
-                    else                              {Fraction::zero()};
-        let var = Rc::new(RefCell::new(Variable::Frac(value)));
+    fn binop_eq(&mut self) -> Result<(), String> {
+        let rhs = self.pop_var()?;
+        let lhs = self.pop_var()?;
+        let value = if lhs.borrow().equals(&rhs.borrow()) {Fraction::one()}
+                    else                                 {Fraction::zero()};
+        let var = new_cell(Variable::Frac(value));
         self.stack.push(StackObject::Var(var));
+        Ok(())
     }
 
-    fn binop_neq(&mut self) {
-        let rhs = self.pop_var();
-        let lhs = self.pop_var();
-        let value = if *lhs.borrow() != *rhs.borrow() {Fraction::one()}
-                    else                              {Fraction::zero()};
-        let var = Rc::new(RefCell::new(Variable::Frac(value)));
+    fn binop_neq(&mut self) -> Result<(), String> {
+        let rhs = self.pop_var()?;
+        let lhs = self.pop_var()?;
+        let value = if !lhs.borrow().equals(&rhs.borrow()) {Fraction::one()}
+                    else                                  {Fraction::zero()};
+        let var = new_cell(Variable::Frac(value));
         self.stack.push(StackObject::Var(var));
+        Ok(())
     }
 
-    fn uniop_neg(&mut self) {
-        let expr = self.pop_var();
+    fn uniop_neg(&mut self) -> Result<(), String> {
+        let expr = self.pop_var()?;
         let result = match &*expr.borrow() {
             Variable::Frac(x) => Variable::Frac(-x),
-            _ => panic!("The negation operation is only supported by numbers"),
+            _ => return Err(String::from("The negation operation is only supported by numbers")),
         };
-        self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+        self.stack.push(StackObject::Var(new_cell(result)));
+        Ok(())
     }
 
-    fn uniop_not(&mut self) {
-        let expr = self.pop_var();
+    fn uniop_not(&mut self) -> Result<(), String> {
+        let expr = self.pop_var()?;
         let result = if expr.borrow().to_bool() {
             Variable::Frac(Fraction::zero())
         } else {
             Variable::Frac(Fraction::one())
         };
         self.stack.push(
-            StackObject::Var(Rc::new(RefCell::new(result)))
+            StackObject::Var(new_cell(result))
         );
+        Ok(())
     }
 
-    fn uniop_len(&mut self) {
-        let expr = self.pop_var();
-        let len = expr.borrow().get_array_length();
+    fn uniop_bitnot(&mut self) -> Result<(), String> {
+        let expr = self.pop_var()?;
+        let result = match &*expr.borrow() {
+            Variable::Frac(value) => Variable::Frac(Fraction::from_integer(!fraction_to_integer(value, ".~")?)),
+            Variable::Int(int) => Variable::Int(FixedInt::new(!int.value, int.bits)),
+            _ => return Err(String::from("Applying uniop \".~\" to incompatible type"))
+        };
         self.stack.push(
-            StackObject::Var(Rc::new(RefCell::new(
-                Variable::Frac(Fraction::from_integer(
-                    num_bigint::BigInt::from(len)
-                ))
-            )))
+            StackObject::Var(new_cell(result))
         );
+        Ok(())
     }
 
-    fn pull(&mut self, register: usize) {
-        let new_var = match &mut *self.pop_var().borrow_mut() {
+    fn cast_int(&mut self, bits: u32) -> Result<(), String> {
+        let expr = self.pop_var()?;
+        let result = match &*expr.borrow() {
+            Variable::Frac(value) => FixedInt::from_fraction(value, bits)?,
+            Variable::Int(int) => FixedInt::new(int.value, bits),
+            other => return Err(format!("Converting \"{}\" to u{}", other, bits))
+        };
+        self.stack.push(StackObject::Var(new_cell(Variable::Int(result))));
+        Ok(())
+    }
+
+    fn cast_frac(&mut self) -> Result<(), String> {
+        let expr = self.pop_var()?;
+        let result = match &*expr.borrow() {
+            Variable::Frac(value) => value.clone(),
+            Variable::Int(int) => int.to_fraction(),
+            other => return Err(format!("Converting \"{}\" to a fraction", other))
+        };
+        self.stack.push(StackObject::Var(new_cell(Variable::Frac(result))));
+        Ok(())
+    }
+
+    fn uniop_len(&mut self) -> Result<(), String> {
+        let expr = self.pop_var()?;
+        let len = expr.borrow().get_array_length()?;
+        self.stack.push(
+            StackObject::Var(new_cell(
+                Variable::Frac(Fraction::from(len as i64))
+            ))
+        );
+        Ok(())
+    }
+
+    fn pull(&mut self, register: usize) -> Result<(), String> {
+        let new_var = match &mut *self.pop_var()?.borrow_mut() {
             Variable::Array(items) => match items.pop() {
                 Some(item) => item,
-                None => panic!("Pulling from empty array")
+                None => return Err(String::from("Pulling from empty array"))
+            },
+            Variable::Str(string) => match string.pop() {
+                Some(c) => new_cell(Variable::Str(c.to_string())),
+                None => return Err(String::from("Pulling from empty string"))
             },
-            _ => panic!("Pulling is only supported by arrays")
+            _ => return Err(String::from("Pulling is only supported by arrays and strings"))
         };
         replace(
             self.registers.get_mut(register).unwrap(),
             Some(new_var)
         );
+        Ok(())
     }
 
-    fn push(&mut self, register: usize) {
-        let src_ref = replace(
-            self.registers.get_mut(register).unwrap(),
-            None
-        ).unwrap();
-        match &mut *self.pop_var().borrow_mut() {
+    fn push(&mut self, register: usize) -> Result<(), String> {
+        let src_ref = match replace(self.registers.get_mut(register).unwrap(), None) {
+            Some(var) => var,
+            None => return Err(self.empty_register_error(self.func_idx, register))
+        };
+        match &mut *self.pop_var()?.borrow_mut() {
             Variable::Array(items) => items.push(src_ref),
-            _ => panic!("Pushing is only supported by arrays")
+            Variable::Str(string) => match &*src_ref.borrow() {
+                Variable::Str(c) if c.chars().count() == 1 => string.push_str(c),
+                other => return Err(format!("Only single characters can be pushed to strings, not \"{}\"", other))
+            },
+            _ => return Err(String::from("Pushing is only supported by arrays and strings"))
+        }
+        Ok(())
+    }
+
+    // Pulling from a map removes a key, otherwise pulls from the indexed item
+    fn pull_indexed(&mut self, register: usize) -> Result<(), String> {
+        let container = self.pop_var()?;
+        let key = self.pop_var()?;
+        if let Variable::Map(entries) = &mut *container.borrow_mut() {
+            let new_var = entries.remove(&key.borrow().to_map_key()?).ok_or_else(
+                || format!("Pulling missing key \"{}\" from map", key.borrow())
+            )?;
+            self.registers[register] = Some(new_var);
+            return Ok(());
+        }
+        let item = container.borrow().subscript(&key.borrow())?;
+        self.stack.push(StackObject::Var(item));
+        self.pull(register)
+    }
+
+    // Pushing to a map inserts a new key, otherwise pushes to the indexed item
+    fn push_indexed(&mut self, register: usize) -> Result<(), String> {
+        let container = self.pop_var()?;
+        let key = self.pop_var()?;
+        if let Variable::Map(entries) = &mut *container.borrow_mut() {
+            let src_ref = match self.registers[register].take() {
+                Some(var) => var,
+                None => return Err(self.empty_register_error(self.func_idx, register))
+            };
+            if entries.insert(key.borrow().to_map_key()?, src_ref).is_some() {
+                return Err(format!("Pushing existing key \"{}\" to map", key.borrow()));
+            }
+            return Ok(());
         }
+        let item = container.borrow().subscript(&key.borrow())?;
+        self.stack.push(StackObject::Var(item));
+        self.push(register)
     }
 
-    fn print(&mut self, count: isize) {
+    fn print(&mut self, count: isize) -> Result<(), String> {
         for _ in 0..count.abs() {
-            print!("{}", self.pop_var().borrow());
+            let var = self.pop_var()?;
+            write!(self.output.0, "{}", var.borrow()).map_err(output_error)?;
         }
         if count < 0 {
-            print!("\n");
+            writeln!(self.output.0).map_err(output_error)?;
         }
+        Ok(())
     } 
 
-    fn create_iter(&mut self, register: usize) {
-        let var = self.pop_var();
-        let array_len = match &*var.borrow() {
-            Variable::Array(array) => array.len(),
-            _ => panic!("For loop iterator is not an array")
+    fn assert(&mut self, line: usize, col: usize) -> Result<(), String> {
+        let condition = self.pop_var()?;
+        if self.options.check_asserts && !condition.borrow().to_bool() {
+            return Err(format!("Assertion failed at line {}, column {}", line, col));
+        }
+        Ok(())
+    }
+
+    fn check_reversible(&mut self, check: ReversibilityCheck, expected: bool) -> Result<(), String> {
+        let condition = self.pop_var()?;
+        if condition.borrow().to_bool() != expected {
+            return Err(check.describe(expected, self.forwards));
+        }
+        Ok(())
+    }
+
+    fn create_iter(&mut self, register: usize, index_register: Option<usize>, over_keys: bool) -> Result<(), String> {
+        let var = self.pop_var()?;
+        let (array_len, keys) = match &*var.borrow() {
+            Variable::Array(_) if over_keys => return Err(String::from("Looping over the keys of an array rather than a map")),
+            Variable::Array(array) => (array.len(), Vec::new()),
+            Variable::Map(entries) => (entries.len(), entries.keys().cloned().collect()),
+            _ => return Err(String::from("For loop iterator is not an array"))
         };
         let idx = if self.forwards {-1}
                   else {array_len as isize};
-        let iter_state = IterState{register, var, idx};
+        let iter_state = IterState{register, index_register, over_keys, keys, var, idx};
         self.stack.push(StackObject::Iter(iter_state));
+        Ok(())
     }
 
-    fn step_iter(&mut self, ip: usize) {
+    fn step_iter(&mut self, ip: usize) -> Result<(), String> {
         // Get iterator state off the stack
-        let (idx, var, register) = match self.stack.last_mut() {
-            Some(StackObject::Iter(IterState{idx, var, register})) => (idx, var.borrow(), *register),
-            _ => panic!("No IterState on the stack")
+        let IterState{idx, var, register, index_register, over_keys, keys} = match self.stack.last_mut() {
+            Some(StackObject::Iter(iter_state)) => iter_state,
+            _ => return Err(String::from("No IterState on the stack"))
         };
-        let array = match &*var {
-            Variable::Array(array) => array,
-            _ => panic!("For loop iterator is not an array")
+        let (register, index_register, over_keys) = (*register, *index_register, *over_keys);
+        let var = var.borrow();
+        let len = match &*var {
+            Variable::Array(array) => array.len(),
+            Variable::Map(_) => keys.len(),
+            _ => return Err(String::from("For loop iterator is not an array"))
         };
 
         // Step iteration, or jump to after loop if iterator exhausted
-        if (self.forwards && *idx == array.len() as isize - 1) || 
+        if (self.forwards && *idx == len as isize - 1) || 
            (!self.forwards && *idx == 0) {
             drop(var);
-            self.pop();
+            self.pop()?;
             self.registers[register] = None;
+            if let Some(index_register) = index_register {
+                self.registers[index_register] = None;
+            }
             self.jump(ip);
         } else {
             *idx += if self.forwards {1} else {-1};
-            self.registers[register] = Some(Rc::clone(&array[*idx as usize]));
+            self.registers[register] = Some(match &*var {
+                Variable::Map(_) if over_keys => new_cell(keys[*idx as usize].to_variable()),
+                Variable::Map(entries) => Rc::clone(&entries[&keys[*idx as usize]]),
+                array => Rc::clone(&array[*idx as usize])
+            });
+            if let Some(index_register) = index_register {
+                self.registers[index_register] = Some(new_cell(Variable::Frac(
+                    Fraction::from(*idx as i64)
+                )));
+            }
             self.ip += 1;
         };
+        Ok(())
     }
 
     #[inline]
-    fn pop(&mut self) -> StackObject {
-        self.stack.pop().expect("Popped off empty stack")
+    fn pop(&mut self) -> Result<StackObject, String> {
+        self.stack.pop().ok_or_else(|| String::from("Popped off empty stack"))
     }
 
     #[inline]
-    fn pop_var(&mut self) -> Rc<RefCell<Variable>> {
-        match self.pop() {
-            StackObject::Var(x) => x,
-            _ => panic!("Non-variable found on the stack")
+    fn pop_var(&mut self) -> Result<Rc<RefCell<Variable>>, String> {
+        match self.pop()? {
+            StackObject::Var(x) => Ok(x),
+            _ => Err(String::from("Non-variable found on the stack"))
         }
     }
 
-    pub fn debug_print(&self) {
-        println!(
-            "registers: {:#?}\nglobals: {:#?}\nStack: {:#?}\n----------", 
-            self.registers,
-            self.global_registers,
-            self.stack);
+    pub fn debug_print(&mut self) -> Result<(), String> {
+        let mut text = String::new();
+        for (heading, variables) in [("locals", self.local_variables()), ("globals", self.global_variables())] {
+            text += &format!("{}:\n", heading);
+            for (name, var) in variables {
+                text += &format!("    {} = {}\n", name, var.borrow());
+            }
+        }
+        text += &format!("Stack: {:#?}\n----------\n", self.stack);
+        self.output.0.write_all(text.as_bytes()).map_err(output_error)
     }
 }
 
+fn output_error(error: io::Error) -> String {
+    format!("Writing output failed: {}", error)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+    use crate::testing::{self, TempProgram};
+
+    // Runs a program from a file the same way main does, giving what it printed and how it ended
+    fn run_file(path: &str, options: &Options) -> (String, Result<(), String>) {
+        let program = testing::compile(path, &compiler::Options::default()).unwrap_or_else(|e| panic!("{}", e));
+        let mut interpreter = Interpreter::new(&program, options);
+        let output = OutputBuffer::new();
+        interpreter.output_to(Box::new(output.clone()));
+        let result = interpreter.run_main(&program).map_err(|e| e.desc);
+        (output.contents(), result)
+    }
+
+    fn run_source(source: &str, options: &Options) -> (String, Result<(), String>) {
+        run_file(TempProgram::new(source).path(), options)
+    }
+
+    // Each example is checked against the output it's known to give, in the .out file beside it
+    fn check_example(name: &str, expected: &str) {
+        let path = format!("{}/examples/{}.mx", env!("CARGO_MANIFEST_DIR"), name);
+        assert_eq!(run_file(&path, &Options::default()), (String::from(expected), Ok(())));
+    }
+
+    #[test]
+    fn compression_example() {
+        check_example("compression", include_str!("../examples/compression.out"));
+    }
+
+    #[test]
+    fn tmp_example() {
+        check_example("tmp", include_str!("../examples/tmp.out"));
+    }
+
+    #[test]
+    fn powers_too_large_to_hold_are_errors() {
+        let pow = |base: i64, numer: i64, denom: i64| exact_pow(&Fraction::from(base), &Fraction::Small(numer, denom));
+        let error = pow(2, i32::MAX as i64, 1).unwrap_err();
+        assert!(error.contains("would give a number of more than"), "{}", error);
+        assert!(pow(3, -(i32::MAX as i64), 1).is_err());
+        assert_eq!(pow(1, i32::MAX as i64, 1), Ok(Fraction::one()));
+        assert_eq!(pow(-1, i32::MAX as i64, 1), Ok(Fraction::from(-1)));
+        assert_eq!(pow(2, 100, 1), Ok(Fraction::new(BigInt::one() << 100, BigInt::one())));
+        assert_eq!(pow(4, 3, 2), Ok(Fraction::from(8)));
+    }
+
+    #[test]
+    fn function_values_can_be_called_in_expressions() {
+        let source = "
+            fn main()() {
+                f = dbl;
+                z = 5;
+                w = f(z) + 1;
+                println(w, ' ', apply(dbl, z), ' ', apply(sq, z));
+            } ~main()
+
+            fn dbl(&x)() { r = x * 2; } ~dbl(r)
+            fn sq(&x)() { r = x * x; } ~sq(r)
+            fn apply(h, x)() { r = h(x); } ~apply(r)";
+        assert_eq!(run_source(source, &Options::default()), (String::from("11 10 25\n"), Ok(())));
+    }
+
+    #[test]
+    fn function_values_print_their_names() {
+        let source = "
+            fn main()() {
+                f = dbl;
+                println(f, ' ', [f, main]);
+            } ~main()
+
+            fn dbl(&x)() { r = x * 2; } ~dbl(r)";
+        let expected = "<function dbl> [<function dbl>, <function main>]\n";
+        assert_eq!(run_source(source, &Options::default()), (String::from(expected), Ok(())));
+    }
+
+    #[test]
+    fn fixed_width_shifts_by_the_width_or_more_give_zero() {
+        let source = "
+            fn main()() {
+                x = 200 as u8;
+                println(x << 1, ' ', x << 8, ' ', x << 300, ' ', x >> 9, ' ', x >> (1 as u32));
+                y = 1 as u64;
+                println(y << 63, ' ', y << 1000000000000, ' ', y << (10 ** 30), ' ', 3 << (2 as u8));
+            } ~main()";
+        let expected = "144 0 0 0 100\n9223372036854775808 0 0 12\n";
+        assert_eq!(run_source(source, &Options::default()), (String::from(expected), Ok(())));
+    }
+
+    #[test]
+    fn summaries_stop_formatting_at_the_trace_width() {
+        let array = |items: Vec<Variable>| Variable::Array(items.into_iter().map(new_cell).collect());
+        let mut nested = Variable::Frac(Fraction::from(12345));
+        for _ in 0..6 {
+            nested = array(vec![nested; 16]);
+        }
+        let summary = nested.summary();
+        assert_eq!(summary.chars().count(), tracer::STACK_SUMMARY_CHARS + 1);
+        assert!(summary.starts_with("[[[[[[12345, 12345, "));
+
+        let small = array(vec![Variable::Frac(Fraction::from(1)), Variable::Str(String::from("two"))]);
+        assert_eq!(small.summary(), "[1, two]");
+        assert_eq!(array((0..20).map(|i| Variable::Frac(Fraction::from(i))).collect()).summary(), "<array of 20 items>");
+    }
+
+    // Runs a program which never finishes until a limit stops it, giving what kind of stop it was
+    fn stop_kind(options: &Options) -> (ErrorKind, u64) {
+        let file = TempProgram::new("
+            fn main()() {
+                i = 0;
+                while (i >= 0) {
+                    i += 1;
+                } ~while (i > 0);
+            } ~main()");
+        let program = testing::compile(file.path(), &compiler::Options::default()).unwrap();
+        let mut interpreter = Interpreter::new(&program, options);
+        let error = interpreter.run_main(&program).unwrap_err();
+        (error.kind, interpreter.instructions_run())
+    }
+
+    #[test]
+    fn running_out_of_fuel_stops_the_program() {
+        let options = Options{fuel: Some(1000), ..Options::default()};
+        assert_eq!(stop_kind(&options), (ErrorKind::OutOfFuel, 1000));
+    }
+
+    #[test]
+    fn passing_the_deadline_stops_the_program() {
+        let options = Options{deadline: Some(Instant::now()), ..Options::default()};
+        assert_eq!(stop_kind(&options).0, ErrorKind::DeadlinePassed);
+    }
+
+    #[test]
+    fn an_interrupt_stops_the_program() {
+        let interrupt = InterruptHandle::new();
+        interrupt.interrupt();
+        let options = Options{interrupt: Some(interrupt), ..Options::default()};
+        assert_eq!(stop_kind(&options).0, ErrorKind::Interrupted);
+    }
+
+    #[test]
+    fn cell_limit_is_cheap_to_check_in_deep_recursion() {
+        let file = TempProgram::new("
+            fn main()() {
+                n = 10000;
+                down(n);
+            } ~main()
+
+            fn down(n)() {
+                if (n > 0) {
+                    n -= 1;
+                    down(n);
+                    n += 1;
+                } ~if (n > 0);
+            } ~down()");
+        let program = testing::compile(file.path(), &compiler::Options::default()).unwrap();
+        let options = Options{max_cells: Some(10), ..Options::default()};
+        let mut interpreter = Interpreter::new(&program, &options);
+        interpreter.run_main(&program).unwrap();
+        // Each count is paid for by the instructions run before the next, apart from the last,
+        // rather than recounting the whole call stack for every cell created near the limit
+        assert!(interpreter.cell_count_work <= 2 * interpreter.instructions_run(),
+                "Counting cells looked at {} things in {} instructions",
+                interpreter.cell_count_work, interpreter.instructions_run());
+    }
+}
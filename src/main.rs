@@ -2,40 +2,164 @@
 
 extern crate num_rational;
 
+use std::env;
 use std::fs;
+use std::io;
+use std::process;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 mod tokeniser;
+mod fraction;
 mod interpreter;
 mod parsetree;
 mod syntaxtree;
 mod syntaxchecker;
 mod compiler;
 mod parser;
+mod resolver;
+mod debugger;
+mod profiler;
+mod tracer;
+#[cfg(test)]
+mod testing;
 
-use syntaxchecker::{check_syntax, SyntaxError};
+use interpreter::{ErrorKind, RuntimeError};
+use resolver::SourceError;
+use syntaxchecker::check_syntax;
 
 
-type Fraction = num_rational::BigRational;
-
 fn main() {
     
-    let src = fs::read_to_string("examples/tmp.mx").expect("File io error");
-    let tokens = tokeniser::tokenise(&src);
-    // println!("Tokens: {:#?}", tokens);
-    let parsed = parser::parse(tokens).expect("Failed to parse");
-
+    let args: Vec<_> = env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+    let debug = args.first().map(String::as_str) == Some("debug");
+    let path = args.get(if debug {1} else {0})
+                   .cloned()
+                   .unwrap_or_else(|| String::from("examples/tmp.mx"));
+    let parsed = match resolver::load_program(&path) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            report_error(error);
+            process::exit(1);
+        }
+    };
 
     let module = match check_syntax(parsed) {
         Ok(module) => module,
-        Err(SyntaxError{line, col, desc}) => {
-            eprintln!("SyntaxError at line {}, column {}:\n ->  {}\n", line, col, desc);
-            return;
+        Err(error) => {
+            report_error(error);
+            process::exit(1);
         }
     };
 
     // println!("Module: {:#?}", module);
-    let program = module.compile();
+    let compile_options = compiler::Options {
+        strict: env::args().any(|arg| arg == "--strict"),
+        debug
+    };
+    let program = module.compile(&compile_options);
     // println!("Compiled: {:#?}", program);
-    interpreter::Interpreter::run(&program);
+    let options = match interpreter_options() {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    };
+    // Undoing statements in the debugger would count them again, so a profile wouldn't mean much
+    if debug && options.profile {
+        eprintln!("--profile and --profile-folded can't be used when debugging");
+        process::exit(1);
+    }
+    let folded_path: Option<String> = parse_flag("profile-folded", "a file name").unwrap_or_default();
+    let mut interpreter = interpreter::Interpreter::new(&program, &options);
+    let output_path: Option<String> = parse_flag("output", "a file name").unwrap_or_default();
+    if let Some(output_path) = output_path {
+        match fs::File::create(&output_path) {
+            Ok(file) => interpreter.output_to(Box::new(io::BufWriter::new(file))),
+            Err(error) => {
+                eprintln!("Couldn't create {}: {}", output_path, error);
+                process::exit(1);
+            }
+        }
+    }
+    let trace_path: Option<String> = parse_flag("trace", "a file name").unwrap_or_default();
+    if let Some(trace_path) = trace_path {
+        let trace_function: Option<String> = parse_flag("trace-function", "a function name").unwrap_or_default();
+        let traced = fs::File::create(&trace_path)
+            .map_err(|error| format!("Couldn't create {}: {}", trace_path, error))
+            .and_then(|file| interpreter.trace_to(Box::new(file), trace_function.as_deref()));
+        if let Err(message) = traced {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    }
+    if debug {
+        debugger::Debugger::new(interpreter, &program, &path, Box::new(io::stdout())).repl();
+        return;
+    }
+    let failed = match interpreter.run_main(&program) {
+        Ok(()) => false,
+        Err(error) => {
+            report_runtime_error(*error);
+            true
+        }
+    };
+    // A program stopped by an error or a limit still gets a profile of what it did up to then
+    if let Some(profiler) = interpreter.profiler() {
+        eprint!("{}", profiler.report(&program.functions));
+        if let Some(folded_path) = folded_path {
+            if let Err(error) = fs::write(&folded_path, profiler.folded_stacks(&program.functions)) {
+                eprintln!("Couldn't write {}: {}", folded_path, error);
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
     
-}
\ No newline at end of file
+}
+
+fn interpreter_options() -> Result<interpreter::Options, String> {
+    let timeout: Option<f64> = parse_flag("timeout", "a number of seconds")?;
+    Ok(interpreter::Options {
+        check_asserts: !env::args().any(|arg| arg == "--no-asserts"),
+        fuel: parse_flag("fuel", "a number of instructions")?,
+        deadline: timeout.map(|timeout| Instant::now() + Duration::from_secs_f64(timeout)),
+        interrupt: None,
+        max_call_depth: parse_flag("max-call-depth", "a number of calls")?,
+        max_stack_size: parse_flag("max-stack-size", "a number of items")?,
+        max_cells: parse_flag("max-cells", "a number of variable cells")?,
+        profile: env::args().any(|arg| arg == "--profile" || arg.starts_with("--profile-folded="))
+    })
+}
+
+// The value of a flag written like --name=value, if it was given
+fn parse_flag<T: FromStr>(name: &str, expected: &str) -> Result<Option<T>, String> {
+    let prefix = format!("--{}=", name);
+    match env::args().find_map(|arg| arg.strip_prefix(&prefix).map(String::from)) {
+        Some(value) => value.parse().map(Some).map_err(|_| format!("--{} takes {}", name, expected)),
+        None => Ok(None)
+    }
+}
+
+fn report_error(SourceError{kind, file, line, col, desc}: SourceError) {
+    eprintln!("{} in {} at line {}, column {}:\n ->  {}\n", kind, file, line, col, desc);
+}
+
+fn report_runtime_error(RuntimeError{kind, desc, function, file, forwards, src_pos, call_stack}: RuntimeError) {
+    let direction = |forwards| if forwards {"forwards"} else {"backwards"};
+    let location = |file: &str, src_pos| match src_pos {
+        Some((line, col)) => format!("{} at line {}, column {}", file, line, col),
+        None => String::from(file)
+    };
+    // Hitting a limit isn't the program's fault, so it's reported as being stopped rather than an error
+    let heading = if kind == ErrorKind::Program {"RuntimeError"} else {"Stopped"};
+    eprintln!("{} in {} running {}, {}:\n ->  {}",
+              heading, function, direction(forwards), location(&file, src_pos), desc);
+    eprintln!("Call stack (most recent call last):");
+    for frame in call_stack {
+        eprintln!("    {} ({}), {}", frame.function, direction(frame.forwards), location(&frame.file, frame.src_pos));
+    }
+    eprintln!();
+}
@@ -8,7 +8,9 @@ use crate::parsetree::{
     FractionNode, BinopNode, IfNode, ModopNode, FunctionNode,
     CatchNode, ArrayLiteralNode, Module, RefUnrefNode, CallNode,
     FunctionParam, PushPullNode, UniopNode, WhileNode, ForNode,
-    PrintNode, StringNode, DoYieldNode, ArrayRepeatNode
+    PrintNode, StringNode, DoYieldNode, ArrayRepeatNode, LocalNode,
+    AssertNode, TernaryNode, CallExprNode, ImportNode, RecordLiteralNode,
+    FieldNode, MapLiteralNode, CastNode
 };
 use crate::interpreter::{Fraction, Instruction};
 
@@ -22,8 +24,8 @@ pub struct Parser {
 
 #[derive(Debug)]
 pub struct ParseError {
-    line: usize,
-    col: usize
+    pub line: usize,
+    pub col: usize
 }
 
 type VecStatementNode = Vec<StatementNode>;
@@ -269,19 +271,37 @@ impl Parser {
     
     pub fn module(&mut self) -> Option<Module> {
         parse!(self;
+            imports: self.repeat(Parser::import_stmt, true),
             global_stmts: self.repeat(Parser::global_statement, true),
-            functions: self.repeat(Parser::function, false),
+            functions: self.repeat(Parser::function, true),
             _end: self.expect_type("END_MARKER!"),
             {
                 let global_func = FunctionNode {
                     name: String::from("!global!"),
+                    namespace: String::new(),
+                    file: String::new(),
                     owned_links: Vec::new(),
                     borrow_params: Vec::new(),
                     steal_params: Vec::new(),
                     return_params: Vec::new(),
                     stmts: global_stmts                 
                 };
-                return Some(Module{global_func, functions});
+                return Some(Module{imports, global_func, functions, namespace_aliases: HashMap::new()});
+            }
+        );
+        None
+    }
+
+    pub fn import_stmt(&mut self) -> Option<ImportNode> {
+        parse!(self;
+            pos: self.expect_literal_with_src_position("import"),
+            path: self.expect_type("STRING"),
+            "as",
+            alias: self.expect_type("NAME"),
+            ";",
+            {
+                let (line, col) = pos;
+                return Some(ImportNode{line, col, path: path.string_, alias: alias.string_});
             }
         );
         None
@@ -309,7 +329,9 @@ impl Parser {
         let return_params = self.join(Parser::function_param, ",");
         if self.expect_literal(")") {
             return Some(FunctionNode{
-                name, owned_links, borrow_params, steal_params, return_params, stmts
+                name, owned_links, borrow_params, steal_params, return_params, stmts,
+                namespace: String::new(),
+                file: String::new()
             });
         }}}}}}}}}}}};
 
@@ -339,7 +361,9 @@ impl Parser {
         if let Some(stmt) = self.while_stmt() {return Some(stmt);}
         if let Some(stmt) = self.for_stmt() {return Some(stmt);}
         if let Some(stmt) = self.doyield_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.local_stmt() {return Some(stmt);}
         if let Some(stmt) = self.catch_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.assert_stmt() {return Some(stmt);}
         if let Some(stmt) = self.call_stmt() {return Some(stmt);}
         None
     }  
@@ -426,13 +450,13 @@ impl Parser {
     pub fn catch_stmt_(&mut self) -> Option<StatementNode> {
         let pos = self.mark();
 
-        if self.expect_literal("catch") {
+        if let Some((line, col)) = self.expect_literal_with_src_position("catch") {
         if self.expect_literal("(") {
         if let Some(expr) = self.expression() {
         if self.expect_literal(")") {
         if self.expect_literal(";") {
             return Some(Box::new(
-                CatchNode{expr}
+                CatchNode{line, col, expr}
             ));
         }}}}};
 
@@ -440,26 +464,70 @@ impl Parser {
         None
     }
 
+    memoise!(assert_stmt_ as assert_stmt -> StatementNode);
+    pub fn assert_stmt_(&mut self) -> Option<StatementNode> {
+        parse!(self;
+            pos : self.expect_literal_with_src_position("assert"),
+            "(",
+            expr : self.expression(),
+            ")",
+            ";",
+            {
+                let (line, col) = pos;
+                return Some(Box::new(
+                    AssertNode{line, col, expr}
+                ));
+            }
+        );
+        None
+    }
+
     memoise!(doyield_stmt_ as doyield_stmt -> StatementNode);
     pub fn doyield_stmt_(&mut self) -> Option<StatementNode> {
         parse!(self;
-            "do",
+            pos : self.expect_literal_with_src_position("do"),
             "{",
             do_stmts : self.repeat(Parser::statement, true),
             "}",
             yield_stmts : self.yield_block(),
-            "~",
+            end_pos : self.expect_literal_with_src_position("~"),
             "do",
             ";",
             {
+                let ((line, col), (end_line, end_col)) = (pos, end_pos);
                 return Some(Box::new(
-                    DoYieldNode{do_stmts, yield_stmts}
+                    DoYieldNode{line, col, end_line, end_col, do_stmts, yield_stmts}
                 ));
             }
         );
         None
     }
     
+    memoise!(local_stmt_ as local_stmt -> StatementNode);
+    pub fn local_stmt_(&mut self) -> Option<StatementNode> {
+        parse!(self;
+            "local",
+            name_pos : self.name_with_src_position(),
+            "=",
+            init : self.expression(),
+            "{",
+            stmts : self.repeat(Parser::statement, true),
+            "}",
+            end_pos : self.expect_literal_with_src_position("delocal"),
+            exit_name : self.name(),
+            "=",
+            exit : self.expression(),
+            ";",
+            {
+                let ((name, (line, col)), (end_line, end_col)) = (name_pos, end_pos);
+                return Some(Box::new(
+                    LocalNode{line, col, end_line, end_col, name, init, stmts, exit_name, exit}
+                ));
+            }
+        );
+        None
+    }
+
     memoise!(yield_block_ as yield_block -> VecStatementNode);
     pub fn yield_block_(&mut self) -> Option<Vec<StatementNode>> {
         let pos = self.mark();
@@ -478,29 +546,57 @@ impl Parser {
     memoise!(for_stmt_ as for_stmt -> StatementNode);
     pub fn for_stmt_(&mut self) -> Option<StatementNode> {
         parse!(self;
-            "for",
+            pos : self.expect_literal_with_src_position("for"),
             "(",
+            ? index_var : self.for_index(),
             iter_var : self.name(),
             "in",
-            iterator : self.lookup(),
+            iterator : self.for_iterator(),
             ")",
             "{",
             stmts : self.repeat(Parser::statement, true),
-            "}",
+            end_pos : self.expect_literal_with_src_position("}"),
             ";",
             {
+                let ((line, col), (end_line, end_col)) = (pos, end_pos);
+                let (over_keys, iterator) = iterator;
                 return Some(Box::new(
-                    ForNode{iter_var, iterator, stmts}
+                    ForNode{line, col, end_line, end_col, index_var, iter_var, over_keys, iterator, stmts}
                 ));
             }
         );
         None
     }
 
+    // The thing a for loop iterates over, and whether it's the keys of a map. A variable can
+    // still be called keys
+    pub fn for_iterator(&mut self) -> Option<(bool, LookupNode)> {
+        let pos = self.mark();
+        if self.expect_literal("keys") {
+            if let Some(lookup) = self.lookup() {
+                return Some((true, lookup));
+            }
+        }
+        self.reset(pos);
+        self.lookup().map(|lookup| (false, lookup))
+    }
+
+    memoise!(for_index_ as for_index -> String);
+    pub fn for_index_(&mut self) -> Option<String> {
+        parse!(self;
+            index_var : self.name(),
+            ",",
+            {
+                return Some(index_var);
+            }
+        );
+        None
+    }
+
     memoise!(while_stmt_ as while_stmt -> StatementNode);
     pub fn while_stmt_(&mut self) -> Option<StatementNode> {
         parse!(self;
-            "while",
+            pos : self.expect_literal_with_src_position("while"),
             "(",
             fwd_expr : self.expression(),
             ")",
@@ -514,8 +610,9 @@ impl Parser {
             ")",
             ";",
             {
+                let (line, col) = pos;
                 return Some(Box::new(
-                    WhileNode{fwd_expr, stmts, bkwd_expr}
+                    WhileNode{line, col, fwd_expr, stmts, bkwd_expr}
                 ));
             }
         );
@@ -525,7 +622,7 @@ impl Parser {
     memoise!(if_stmt_ as if_stmt -> StatementNode);
     pub fn if_stmt_(&mut self) -> Option<StatementNode> {
         parse!(self;
-            "if",
+            pos : self.expect_literal_with_src_position("if"),
             "(",
             fwd_expr : self.expression(),
             ")",
@@ -548,8 +645,9 @@ impl Parser {
                     Some(expr) => expr,
                     None => fwd_expr.clone()
                 };
+                let (line, col) = pos;
                 return Some(Box::new(
-                    IfNode{fwd_expr, if_stmts, else_stmts, bkwd_expr}
+                    IfNode{line, col, fwd_expr, if_stmts, else_stmts, bkwd_expr}
                 ));
             }
         );
@@ -602,24 +700,24 @@ impl Parser {
     pub fn print_stmt_(&mut self) -> Option<StatementNode> {
         let pos = self.mark();
         
-        if self.expect_literal("print") {
+        if let Some((line, col)) = self.expect_literal_with_src_position("print") {
         if self.expect_literal("(") {
         let items = self.join(Parser::expression, ",");
         if self.expect_literal(")") {
         if self.expect_literal(";") {
             return Some(Box::new(
-                PrintNode{items, newline: false}
+                PrintNode{line, col, items, newline: false}
             ));
         }}}};
         self.reset(pos);
 
-        if self.expect_literal("println") {
+        if let Some((line, col)) = self.expect_literal_with_src_position("println") {
         if self.expect_literal("(") {
         let items = self.join(Parser::expression, ",");
         if self.expect_literal(")") {
         if self.expect_literal(";") {
             return Some(Box::new(
-                PrintNode{items, newline: true}
+                PrintNode{line, col, items, newline: true}
             ));
         }}}};
 
@@ -702,11 +800,29 @@ impl Parser {
     }
 
 
-    memoise_recursive!(expression_ as expression -> ExpressionNode);
+    memoise!(expression_ as expression -> ExpressionNode);
     pub fn expression_(&mut self) -> Option<ExpressionNode> {
+        parse!(self;
+            condition : self.disjunction(),
+            "?",
+            if_expr : self.expression(),
+            ":",
+            else_expr : self.expression(),
+            {
+                return Some(Box::new(
+                    TernaryNode{condition, if_expr, else_expr}
+                ));
+            }
+        );
+
+        self.disjunction()
+    }
+
+    memoise_recursive!(disjunction_ as disjunction -> ExpressionNode);
+    pub fn disjunction_(&mut self) -> Option<ExpressionNode> {
         let pos = self.mark();
         
-        if let Some(lhs) = self.expression() {
+        if let Some(lhs) = self.disjunction() {
         if self.expect_literal("|") {
         if let Some(rhs) = self.expr0() {
             return Some(Box::new(
@@ -765,6 +881,71 @@ impl Parser {
             else if self.expect_literal("==") {Some(Instruction::BinopEq)}
             else                              {None};
         if let Some(instruction) = instruction_match {
+        if let Some(rhs) = self.bit_or() {
+            return Some(Box::new(
+                BinopNode{lhs, rhs, op: instruction}
+            ));
+        }}};
+        self.reset(pos);
+
+        self.bit_or()
+    }
+
+    memoise_recursive!(bit_or_ as bit_or -> ExpressionNode);
+    pub fn bit_or_(&mut self) -> Option<ExpressionNode> {
+        parse!(self;
+            lhs : self.bit_or(),
+            ".|",
+            rhs : self.bit_xor(),
+            {
+                return Some(Box::new(
+                    BinopNode{lhs, rhs, op: Instruction::BinopBitOr}
+                ));
+            }
+        );
+        self.bit_xor()
+    }
+
+    memoise_recursive!(bit_xor_ as bit_xor -> ExpressionNode);
+    pub fn bit_xor_(&mut self) -> Option<ExpressionNode> {
+        parse!(self;
+            lhs : self.bit_xor(),
+            ".^",
+            rhs : self.bit_and(),
+            {
+                return Some(Box::new(
+                    BinopNode{lhs, rhs, op: Instruction::BinopBitXor}
+                ));
+            }
+        );
+        self.bit_and()
+    }
+
+    memoise_recursive!(bit_and_ as bit_and -> ExpressionNode);
+    pub fn bit_and_(&mut self) -> Option<ExpressionNode> {
+        parse!(self;
+            lhs : self.bit_and(),
+            ".&",
+            rhs : self.shift(),
+            {
+                return Some(Box::new(
+                    BinopNode{lhs, rhs, op: Instruction::BinopBitAnd}
+                ));
+            }
+        );
+        self.shift()
+    }
+
+    memoise_recursive!(shift_ as shift -> ExpressionNode);
+    pub fn shift_(&mut self) -> Option<ExpressionNode> {
+        let pos = self.mark();
+
+        if let Some(lhs) = self.shift() {
+        let instruction_match =
+            if      self.expect_literal("<<") {Some(Instruction::BinopShl)}
+            else if self.expect_literal(">>") {Some(Instruction::BinopShr)}
+            else                              {None};
+        if let Some(instruction) = instruction_match {
         if let Some(rhs) = self.expr3() {
             return Some(Box::new(
                 BinopNode{lhs, rhs, op: instruction}
@@ -828,13 +1009,28 @@ impl Parser {
 
         if let Some(lhs) = self.expr5() {
         if self.expect_literal("**") {
-        if let Some(rhs) = self.atom() {
+        if let Some(rhs) = self.cast() {
             return Some(Box::new(
                 BinopNode{lhs, rhs, op: Instruction::BinopPow}
             ));
         }}};
         self.reset(pos);
 
+        self.cast()
+    }
+
+    memoise_recursive!(cast_ as cast -> ExpressionNode);
+    pub fn cast_(&mut self) -> Option<ExpressionNode> {
+        parse!(self;
+            expr : self.cast(),
+            pos : self.expect_literal_with_src_position("as"),
+            type_name : self.expect_type("NAME"),
+            {
+                return Some(Box::new(
+                    CastNode{line: pos.0, col: pos.1, expr, type_name: type_name.string_}
+                ));
+            }
+        );
         self.atom()
     }
 
@@ -857,6 +1053,18 @@ impl Parser {
             return Some(Box::new(array));
         };
 
+        if let Some(record) = self.record_literal() {
+            return Some(record);
+        };
+
+        if let Some(map) = self.map_literal() {
+            return Some(map);
+        };
+
+        if let Some(call) = self.call_expr() {
+            return Some(call);
+        };
+
         if let Some(lookup) = self.lookup() {
             return Some(Box::new(lookup));
         };
@@ -897,6 +1105,14 @@ impl Parser {
         }};
         self.reset(pos);
 
+        if let Some((line, col)) = self.expect_literal_with_src_position(".~") {
+        if let Some(expr) = self.atom() {
+            return Some(Box::new(
+                UniopNode{expr, line, col, op: Instruction::UniopBitNot}
+            ));
+        }};
+        self.reset(pos);
+
         if let Some((line, col)) = self.expect_literal_with_src_position("#") {
         if let Some(expr) = self.atom() {
             return Some(Box::new(
@@ -908,6 +1124,27 @@ impl Parser {
         None
     }
 
+    memoise!(call_expr_ as call_expr -> ExpressionNode);
+    pub fn call_expr_(&mut self) -> Option<ExpressionNode> {
+        parse!(self;
+            name : self.expect_type("NAME"),
+            "(",
+            borrow_args : Some(self.join(Parser::lookup, ",")),
+            ")",
+            {
+                return Some(Box::new(
+                    CallExprNode{
+                        name: name.string_,
+                        line: name.line,
+                        col: name.col,
+                        borrow_args
+                    }
+                ));
+            }
+        );
+        None
+    }
+
     memoise!(array_literal_ as array_literal -> ArrayLiteralNode);
     pub fn array_literal_(&mut self) -> Option<ArrayLiteralNode> {
         let pos = self.mark();
@@ -922,6 +1159,61 @@ impl Parser {
         None
     }
 
+    memoise!(record_literal_ as record_literal -> ExpressionNode);
+    pub fn record_literal_(&mut self) -> Option<ExpressionNode> {
+        parse!(self;
+            pos : self.expect_literal_with_src_position("{"),
+            fields : Some(self.join(Parser::record_field, ",")).filter(|f| !f.is_empty()),
+            "}",
+            {
+                return Some(Box::new(
+                    RecordLiteralNode{line: pos.0, col: pos.1, fields}
+                ));
+            }
+        );
+        None
+    }
+
+    pub fn record_field(&mut self) -> Option<(String, ExpressionNode)> {
+        parse!(self;
+            name : self.expect_type("NAME"),
+            ":",
+            value : self.expression(),
+            {
+                return Some((name.string_, value));
+            }
+        );
+        None
+    }
+
+    // An empty pair of braces is an empty map rather than a record with no fields
+    memoise!(map_literal_ as map_literal -> ExpressionNode);
+    pub fn map_literal_(&mut self) -> Option<ExpressionNode> {
+        parse!(self;
+            pos : self.expect_literal_with_src_position("{"),
+            entries : Some(self.join(Parser::map_entry, ",")),
+            "}",
+            {
+                return Some(Box::new(
+                    MapLiteralNode{line: pos.0, col: pos.1, entries}
+                ));
+            }
+        );
+        None
+    }
+
+    pub fn map_entry(&mut self) -> Option<(ExpressionNode, ExpressionNode)> {
+        parse!(self;
+            key : self.expression(),
+            "=>",
+            value : self.expression(),
+            {
+                return Some((key, value));
+            }
+        );
+        None
+    }
+
     memoise!(array_repeat_ as array_repeat -> ArrayRepeatNode);
     pub fn array_repeat_(&mut self) -> Option<ArrayRepeatNode> {
         let pos = self.mark();
@@ -945,6 +1237,7 @@ impl Parser {
         if self.expect_literal("-=") { return Some(Instruction::BinopSub) };
         if self.expect_literal("*=") { return Some(Instruction::BinopMul) };
         if self.expect_literal("/=") { return Some(Instruction::BinopDiv) };
+        if self.expect_literal("^=") { return Some(Instruction::BinopBitXor) };
         None
     }
 
@@ -954,7 +1247,12 @@ impl Parser {
         let pos = self.mark();
 
         if let Some((name, (line, col))) = self.name_with_src_position() {
-        if let Some(indices) = self.repeat(Parser::index, true) {
+        if let Some(path) = self.repeat(Parser::path, true) {
+            let (name, fields) = split_field_names(&name);
+            let indices = fields.into_iter()
+                                .map(|name| Box::new(FieldNode{line, col, name}) as ExpressionNode)
+                                .chain(path.into_iter().flatten())
+                                .collect();
             return Some(LookupNode{name, indices, line, col});
         }};
 
@@ -962,6 +1260,26 @@ impl Parser {
         None
     }
 
+    pub fn path(&mut self) -> Option<Vec<ExpressionNode>> {
+        if let Some(index) = self.index() {
+            return Some(vec![index]);
+        }
+
+        parse!(self;
+            pos : self.expect_literal_with_src_position("."),
+            name : self.expect_type("NAME"),
+            {
+                let (line, col) = pos;
+                return Some(
+                    name.string_.split('.')
+                                .map(|name| Box::new(FieldNode{line, col, name: name.to_string()}) as ExpressionNode)
+                                .collect()
+                );
+            }
+        );
+        None
+    }
+
     memoise!(index_ as index -> ExpressionNode);
     pub fn index_(&mut self) -> Option<ExpressionNode> {
         let pos = self.mark();
@@ -996,4 +1314,14 @@ impl Parser {
         None
     }
 
-}
\ No newline at end of file
+}
+
+
+// The tokeniser allows dots inside names, which separate a variable name from the record
+// fields looked up in it, e.g. "p.x.y". A leading dot marks a mono variable instead
+fn split_field_names(name: &str) -> (String, Vec<String>) {
+    let prefix = if name.starts_with('.') {"."} else {""};
+    let mut parts = name[prefix.len()..].split('.');
+    let var_name = format!("{}{}", prefix, parts.next().unwrap());
+    (var_name, parts.map(String::from).collect())
+}
@@ -1,4 +1,5 @@
 
+use std::collections::HashMap;
 use std::fmt;
 
 use crate::interpreter;
@@ -14,6 +15,9 @@ pub trait Expression: fmt::Debug + ExpressionClone {
 
     fn get_src_pos(&self) 
         -> (usize, usize);
+
+    // The field name, if this expression is a record field in a lookup path
+    fn field_name(&self) -> Option<&str> {None}
 }
 
 pub type ExpressionNode = Box<dyn Expression>;
@@ -68,6 +72,27 @@ pub struct ArrayRepeatNode {
     pub dimensions: ExpressionNode
 }
 
+#[derive(Clone, Debug)]
+pub struct RecordLiteralNode {
+    pub line: usize,
+    pub col: usize,
+    pub fields: Vec<(String, ExpressionNode)>
+}
+
+#[derive(Clone, Debug)]
+pub struct MapLiteralNode {
+    pub line: usize,
+    pub col: usize,
+    pub entries: Vec<(ExpressionNode, ExpressionNode)>
+}
+
+#[derive(Clone, Debug)]
+pub struct FieldNode {
+    pub line: usize,
+    pub col: usize,
+    pub name: String
+}
+
 #[derive(Clone, Debug)]
 pub struct LookupNode {
     pub line: usize,
@@ -83,6 +108,14 @@ pub struct BinopNode {
     pub op: interpreter::Instruction
 }
 
+#[derive(Clone, Debug)]
+pub struct CastNode {
+    pub line: usize,
+    pub col: usize,
+    pub expr: ExpressionNode,
+    pub type_name: String
+}
+
 #[derive(Clone, Debug)]
 pub struct UniopNode {
     pub line: usize,
@@ -91,6 +124,21 @@ pub struct UniopNode {
     pub op: interpreter::Instruction
 }
 
+#[derive(Clone, Debug)]
+pub struct CallExprNode {
+    pub line: usize,
+    pub col: usize,
+    pub name: String,
+    pub borrow_args: Vec<LookupNode>
+}
+
+#[derive(Clone, Debug)]
+pub struct TernaryNode {
+    pub condition: ExpressionNode,
+    pub if_expr: ExpressionNode,
+    pub else_expr: ExpressionNode
+}
+
 
 pub trait Statement: fmt::Debug + StatementClone {
     fn to_syntax_node(
@@ -118,6 +166,8 @@ impl Clone for StatementNode {
 
 #[derive(Clone, Debug)]
 pub struct PrintNode {
+    pub line: usize,
+    pub col: usize,
     pub items: Vec<ExpressionNode>,
     pub newline: bool
 }
@@ -158,6 +208,8 @@ pub struct PushPullNode {
 
 #[derive(Clone, Debug)]
 pub struct IfNode {
+    pub line: usize,
+    pub col: usize,
     pub fwd_expr: ExpressionNode,
     pub if_stmts: Vec<StatementNode>,
     pub else_stmts: Vec<StatementNode>,
@@ -166,6 +218,8 @@ pub struct IfNode {
 
 #[derive(Clone, Debug)]
 pub struct WhileNode {
+    pub line: usize,
+    pub col: usize,
     pub fwd_expr: ExpressionNode,
     pub stmts: Vec<StatementNode>,
     pub bkwd_expr: Option<ExpressionNode>
@@ -173,20 +227,52 @@ pub struct WhileNode {
 
 #[derive(Clone, Debug)]
 pub struct ForNode {
+    pub line: usize,
+    pub col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+    pub index_var: Option<String>,
     pub iter_var: String,
+    pub over_keys: bool,
     pub iterator: LookupNode,
     pub stmts: Vec<StatementNode>
 }
 
+#[derive(Clone, Debug)]
+pub struct LocalNode {
+    pub line: usize,
+    pub col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+    pub name: String,
+    pub init: ExpressionNode,
+    pub stmts: Vec<StatementNode>,
+    pub exit_name: String,
+    pub exit: ExpressionNode
+}
+
 #[derive(Clone, Debug)]
 pub struct DoYieldNode {
+    pub line: usize,
+    pub col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
     pub do_stmts: Vec<StatementNode>,
     pub yield_stmts: Vec<StatementNode>
 }
 
+#[derive(Clone, Debug)]
+pub struct AssertNode {
+    pub line: usize,
+    pub col: usize,
+    pub expr: ExpressionNode
+}
+
 
 #[derive(Clone, Debug)]
 pub struct CatchNode {
+    pub line: usize,
+    pub col: usize,
     pub expr: ExpressionNode
 }
 
@@ -211,6 +297,8 @@ pub struct FunctionParam {
 #[derive(Clone, Debug)]
 pub struct FunctionNode {
     pub name: String,
+    pub namespace: String,
+    pub file: String,
     pub owned_links: Vec<String>,
     pub borrow_params: Vec<FunctionParam>,
     pub steal_params: Vec<FunctionParam>,
@@ -218,8 +306,18 @@ pub struct FunctionNode {
     pub stmts: Vec<StatementNode>
 }
 
+#[derive(Clone, Debug)]
+pub struct ImportNode {
+    pub line: usize,
+    pub col: usize,
+    pub path: String,
+    pub alias: String
+}
+
 #[derive(Clone, Debug)]
 pub struct Module {
+    pub imports: Vec<ImportNode>,
     pub global_func: FunctionNode,
-    pub functions: Vec<FunctionNode>
+    pub functions: Vec<FunctionNode>,
+    pub namespace_aliases: HashMap<String, String>  // Namespaces of files imported again, and where they were first loaded
 }
\ No newline at end of file
@@ -0,0 +1,189 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::interpreter::Function;
+
+
+// Where the interpreter is: which function, running in which direction, at which line, and
+// how many calls deep. Instructions not belonging to any statement have no line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub func_idx: usize,
+    pub forwards: bool,
+    pub line: Option<usize>,
+    pub depth: usize
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    instructions: u64,
+    time: Duration
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.instructions += other.instructions;
+        self.time += other.time;
+    }
+}
+
+
+// Counts instructions and time, which are only added to the totals when the position changes
+// to keep the cost per instruction down
+#[derive(Debug)]
+pub struct Profiler {
+    lines: HashMap<(usize, bool, Option<usize>), Counts>,
+    stacks: HashMap<Vec<(usize, bool)>, Counts>,  // Keyed by (function, forwards) from the outermost call
+    position: Option<Position>,
+    stack: Vec<(usize, bool)>,
+    instructions: u64,
+    since: Instant
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            lines: HashMap::new(),
+            stacks: HashMap::new(),
+            position: None,
+            stack: Vec::new(),
+            instructions: 0,
+            since: Instant::now()
+        }
+    }
+
+    pub fn is_at(&self, position: Position) -> bool {
+        self.position == Some(position)
+    }
+
+    pub fn move_to(&mut self, position: Position, stack: Vec<(usize, bool)>) {
+        self.flush();
+        self.position = Some(position);
+        self.stack = stack;
+    }
+
+    #[inline]
+    pub fn count_instruction(&mut self) {
+        self.instructions += 1;
+    }
+
+    // Stops the clock while the interpreter isn't running, such as when the debugger is paused
+    pub fn pause(&mut self) {
+        self.flush();
+        self.position = None;
+    }
+
+    fn flush(&mut self) {
+        let now = Instant::now();
+        if let Some(Position{func_idx, forwards, line, ..}) = self.position {
+            let counts = Counts{instructions: self.instructions, time: now - self.since};
+            self.lines.entry((func_idx, forwards, line)).or_default().add(counts);
+            self.stacks.entry(self.stack.clone()).or_default().add(counts);
+        }
+        self.instructions = 0;
+        self.since = now;
+    }
+
+    // Totals for each function in each direction, then for each line, with the slowest first
+    pub fn report(&self, functions: &[Function]) -> String {
+        let mut totals: HashMap<(usize, bool), Counts> = HashMap::new();
+        for (&(func_idx, forwards, _), counts) in &self.lines {
+            totals.entry((func_idx, forwards)).or_default().add(*counts);
+        }
+        let total_time: Duration = totals.values().map(|counts| counts.time).sum();
+        let percentage = |time: Duration| {
+            if total_time.as_nanos() == 0 {0.0} else {100.0 * time.as_secs_f64() / total_time.as_secs_f64()}
+        };
+
+        let mut report = format!("{:<30} {:>14} {:>12} {:>7}\n", "Function", "Instructions", "Time (ms)", "Time %");
+        let mut totals: Vec<_> = totals.into_iter().collect();
+        totals.sort_by_key(|(_, counts)| Reverse(counts.time));
+        for ((func_idx, forwards), counts) in totals {
+            report += &format!("{:<30} {:>14} {:>12.3} {:>7.1}\n",
+                               frame_name(functions, func_idx, forwards), counts.instructions,
+                               counts.time.as_secs_f64() * 1000.0, percentage(counts.time));
+        }
+
+        report += &format!("\n{:<30} {:<30} {:>14} {:>12} {:>7}\n", "Line", "Function", "Instructions", "Time (ms)", "Time %");
+        let mut lines: Vec<_> = self.lines.iter().collect();
+        lines.sort_by_key(|(_, counts)| Reverse(counts.time));
+        for (&(func_idx, forwards, line), counts) in lines {
+            let line = line.map(|line| line.to_string()).unwrap_or_else(|| String::from("-"));
+            report += &format!("{:<30} {:<30} {:>14} {:>12.3} {:>7.1}\n",
+                               format!("{}:{}", functions[func_idx].file, line), frame_name(functions, func_idx, forwards),
+                               counts.instructions, counts.time.as_secs_f64() * 1000.0, percentage(counts.time));
+        }
+        report
+    }
+
+    // One line per call stack, with the frames separated by semicolons and followed by the time
+    // spent there in microseconds, which is the format flamegraph tools read
+    pub fn folded_stacks(&self, functions: &[Function]) -> String {
+        let mut lines: Vec<_> = self.stacks.iter().map(|(stack, counts)| {
+            let frames: Vec<_> = stack.iter()
+                .map(|&(func_idx, forwards)| frame_name(functions, func_idx, forwards))
+                .collect();
+            format!("{} {}\n", frames.join(";"), counts.time.as_micros())
+        }).collect();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+
+// Functions running backwards are shown as uncalls
+fn frame_name(functions: &[Function], func_idx: usize, forwards: bool) -> String {
+    let name = &functions[func_idx].name;
+    if forwards {name.clone()} else {format!("~{}", name)}
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler;
+    use crate::interpreter::{Interpreter, Options, OutputBuffer};
+    use crate::testing::{self, TempProgram};
+
+    #[test]
+    fn profiles_count_each_function_in_each_direction() {
+        let file = TempProgram::new("
+            fn main()() {
+                x = 0;
+                add(x);
+                add(x);
+                ~add(x);
+                println(x);
+            } ~main()
+
+            fn add(n)() {
+                n += 1;
+            } ~add()");
+        let program = testing::compile(file.path(), &compiler::Options::default()).unwrap();
+        let options = Options{profile: true, ..Options::default()};
+        let mut interpreter = Interpreter::new(&program, &options);
+        interpreter.output_to(Box::new(OutputBuffer::new()));
+        interpreter.run_main(&program).unwrap();
+        let profiler = interpreter.profiler().unwrap();
+
+        // The first table gives each function's instructions, before a blank line and the lines table
+        let report = profiler.report(&program.functions);
+        let mut counts: Vec<(String, u64)> = report.lines().skip(1).take_while(|row| !row.is_empty()).map(|row| {
+            let columns: Vec<_> = row.split_whitespace().collect();
+            (String::from(columns[0]), columns[1].parse().unwrap())
+        }).collect();
+        counts.sort();
+        let expected = [("add", 12), ("main", 11), ("~add", 6)];
+        assert_eq!(counts, expected.map(|(name, count)| (String::from(name), count)));
+        assert_eq!(counts.iter().map(|(_, count)| count).sum::<u64>(), interpreter.instructions_run());
+
+        // Each folded line is a call stack and a whole number of microseconds
+        let folded = profiler.folded_stacks(&program.functions);
+        let stacks: Vec<_> = folded.lines().map(|line| {
+            let (stack, micros) = line.rsplit_once(' ').unwrap();
+            assert!(micros.parse::<u64>().is_ok(), "{}", line);
+            stack
+        }).collect();
+        assert_eq!(stacks, ["<global scope>;main", "<global scope>;main;add", "<global scope>;main;~add"]);
+    }
+}
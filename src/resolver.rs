@@ -0,0 +1,133 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::parser;
+use crate::parsetree as PT;
+use crate::tokeniser;
+
+
+#[derive(Debug)]
+pub struct SourceError {
+    pub kind: &'static str,
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+    pub desc: String
+}
+
+struct ImportSite<'a> {
+    file: &'a str,
+    line: usize,
+    col: usize
+}
+
+impl<'a> ImportSite<'a> {
+    fn error(&self, desc: String) -> SourceError {
+        SourceError{kind: "ImportError", file: self.file.to_string(), line: self.line, col: self.col, desc}
+    }
+}
+
+
+struct Resolver {
+    loading: Vec<PathBuf>,  // Stack of files currently being loaded, to detect import cycles
+    loaded: HashMap<PathBuf, String>,  // Namespace of each file which has finished loading
+    namespace_aliases: HashMap<String, String>,
+    functions: Vec<PT::FunctionNode>
+}
+
+
+// Load a source file and everything it imports into a single module. Functions from
+// imported files are renamed into the namespace of their import alias, e.g. "lib.sort".
+// A file imported more than once is only loaded the first time, and the namespaces it's
+// imported into afterwards become aliases of the first one
+pub fn load_program(path: &str) -> Result<PT::Module, SourceError> {
+    let mut resolver = Resolver{
+        loading: Vec::new(),
+        loaded: HashMap::new(),
+        namespace_aliases: HashMap::new(),
+        functions: Vec::new()
+    };
+    let site = ImportSite{file: path, line: 0, col: 0};
+    let global_func = resolver.load_file(Path::new(path), "", &site)?;
+
+    Ok(PT::Module{
+        imports: Vec::new(),
+        global_func,
+        functions: resolver.functions,
+        namespace_aliases: resolver.namespace_aliases
+    })
+}
+
+
+impl Resolver {
+
+    fn load_file(&mut self, path: &Path, namespace: &str, site: &ImportSite) -> Result<PT::FunctionNode, SourceError> {
+        let file = path.display().to_string();
+        let canonical = canonical_path(path, site)?;
+        if let Some(start) = self.loading.iter().position(|p| *p == canonical) {
+            let cycle = self.loading[start..].iter()
+                                             .chain(std::iter::once(&canonical))
+                                             .map(|p| p.display().to_string())
+                                             .collect::<Vec<_>>();
+            return Err(site.error(format!("Import cycle: {}", cycle.join(" -> "))));
+        }
+        let src = fs::read_to_string(path).map_err(
+            |_| site.error(format!("Can't read file \"{}\"", file))
+        )?;
+
+        let tokens = tokeniser::tokenise(&src);
+        let module = match parser::parse(tokens) {
+            Ok(module) => module,
+            Err(parser::ParseError{line, col}) => {
+                return Err(SourceError{kind: "ParseError", file, line, col, desc: String::from("Failed to parse")});
+            }
+        };
+
+        self.loading.push(canonical);
+
+        let mut aliases = HashSet::new();
+        for import in module.imports {
+            let import_site = ImportSite{file: &file, line: import.line, col: import.col};
+            if !aliases.insert(import.alias.clone()) {
+                return Err(import_site.error(format!("Duplicate import alias \"{}\"", import.alias)));
+            }
+            let import_path = match path.parent() {
+                Some(dir) => dir.join(&import.path),
+                None => PathBuf::from(&import.path)
+            };
+            let import_namespace = format!("{}{}.", namespace, import.alias);
+            if let Some(loaded_namespace) = self.loaded.get(&canonical_path(&import_path, &import_site)?) {
+                self.namespace_aliases.insert(import_namespace, loaded_namespace.clone());
+                continue;
+            }
+            let global_func = self.load_file(&import_path, &import_namespace, &import_site)?;
+            if !global_func.stmts.is_empty() {
+                return Err(import_site.error(format!(
+                    "Imported file \"{}\" contains global statements", import.path)));
+            }
+        }
+
+        for mut function in module.functions {
+            function.name = format!("{}{}", namespace, function.name);
+            function.namespace = namespace.to_string();
+            function.file = file.clone();
+            self.functions.push(function);
+        }
+
+        if let Some(canonical) = self.loading.pop() {
+            self.loaded.insert(canonical, namespace.to_string());
+        }
+
+        let mut global_func = module.global_func;
+        global_func.namespace = namespace.to_string();
+        global_func.file = file;
+        Ok(global_func)
+    }
+}
+
+fn canonical_path(path: &Path, site: &ImportSite) -> Result<PathBuf, SourceError> {
+    fs::canonicalize(path).map_err(
+        |_| site.error(format!("Can't find file \"{}\"", path.display()))
+    )
+}
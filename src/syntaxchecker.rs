@@ -1,6 +1,7 @@
 
 use std::collections::{HashSet, HashMap};
 use std::cell::RefCell;
+use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::mem;
 use std::rc::Rc;
@@ -8,6 +9,7 @@ use std::rc::Rc;
 use num_traits::identities::Zero;
 
 use crate::interpreter;
+use crate::resolver::SourceError;
 use crate::parsetree as PT;
 use crate::syntaxtree as ST;
 
@@ -19,6 +21,14 @@ pub struct Variable {
     id: isize,
     exteriors: RefCell<HashSet<String>>,
     interiors: RefCell<HashSet<String>>,
+    function: RefCell<Option<FunctionSource>>,
+}
+
+// Where the function held by a variable comes from, if it holds one
+#[derive(Clone, Debug, PartialEq)]
+pub enum FunctionSource {
+    Known(String),  // Qualified name of the function
+    Param(usize)    // Index of the borrowed parameter of the enclosing function
 }
 
 impl Hash for Variable{
@@ -39,6 +49,7 @@ pub struct Reference {
     is_interior: bool,
     is_borrowed: bool,
     is_global: bool,
+    is_readonly: bool,
     register: usize,
     var: Rc<Variable>
 }
@@ -50,10 +61,66 @@ pub struct SyntaxError {
     pub desc: String
 }
 
+impl SyntaxError {
+    fn in_file(self, file: String) -> SourceError {
+        SourceError{kind: "SyntaxError", file, line: self.line, col: self.col, desc: self.desc}
+    }
+}
+
+
+// The numbers of borrowed, stolen and returned parameters a called function value must have
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallShape {
+    borrows: usize,
+    steals: usize,
+    returns: usize
+}
+
+impl CallShape {
+    fn of(proto: &ST::FunctionPrototype) -> CallShape {
+        CallShape{
+            borrows: proto.borrow_params.len(),
+            steals: proto.steal_params.len(),
+            returns: proto.return_params.len()
+        }
+    }
+}
+
+impl fmt::Display for CallShape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({} borrowed, {} stolen, {} returned)", self.borrows, self.steals, self.returns)
+    }
+}
+
+// A variable passed as a borrowed argument, which must hold a function if the callee calls it
+#[derive(Debug)]
+pub struct FunctionArg {
+    caller: String,
+    line: usize,
+    col: usize,
+    name: String,
+    callee: String,
+    param: usize,
+    source: Option<FunctionSource>
+}
+
+// Whether a parameter is called can depend on functions which haven't been checked yet,
+// so the shapes of called parameters and the arguments passed to them are collected and
+// resolved once every function has been checked
+#[derive(Debug, Default)]
+pub struct FunctionValueChecks {
+    shapes: HashMap<(String, usize), CallShape>,
+    args: Vec<FunctionArg>
+}
+
 
 #[derive(Debug)]
 pub struct SyntaxContext<'a> {
     functions: &'a HashMap<String, ST::FunctionPrototype>,
+    namespace_aliases: &'a HashMap<String, String>,
+    namespace: &'a str,
+    function: &'a str,
+    function_checks: &'a RefCell<FunctionValueChecks>,
     consts: Vec<interpreter::Variable>,
     strings: Vec<String>,
     free_registers: Vec<usize>,
@@ -61,6 +128,11 @@ pub struct SyntaxContext<'a> {
     locals_stack: Vec<HashMap<String, Reference>>,
     globals: &'a HashMap<String, Reference>,
     num_registers: usize,
+    symbols: Vec<interpreter::Symbol>,
+    // The statement being checked, which any variables created or removed are recorded against
+    stmt_pos: Option<(usize, usize)>,
+    // Variables which can't be modified in the statements being checked, whatever they're called
+    readonly_vars: HashSet<isize>,
     last_var_id: isize
 }
 
@@ -68,10 +140,18 @@ pub struct SyntaxContext<'a> {
 impl<'a> SyntaxContext<'a> {
     pub fn new(
         functions: &'a HashMap<String, ST::FunctionPrototype>,
-        globals: &'a HashMap<String, Reference>
+        namespace_aliases: &'a HashMap<String, String>,
+        globals: &'a HashMap<String, Reference>,
+        namespace: &'a str,
+        function: &'a str,
+        function_checks: &'a RefCell<FunctionValueChecks>
     ) -> SyntaxContext<'a> {
         SyntaxContext {
             functions,
+            namespace_aliases,
+            namespace,
+            function,
+            function_checks,
             consts: Vec::new(),
             strings: Vec::new(),
             free_registers: Vec::new(),
@@ -79,6 +159,9 @@ impl<'a> SyntaxContext<'a> {
             locals_stack: Vec::new(),
             globals,
             num_registers: 0,
+            symbols: Vec::new(),
+            stmt_pos: None,
+            readonly_vars: HashSet::new(),
             last_var_id: 0
         }
     }
@@ -96,10 +179,12 @@ impl<'a> SyntaxContext<'a> {
             register,
             is_borrowed,
             is_global: false,
+            is_readonly: false,
             var: Rc::new(Variable{
                 id: self.new_variable_id(),
                 exteriors: RefCell::new(exteriors),
-                interiors: RefCell::new(HashSet::new())
+                interiors: RefCell::new(HashSet::new()),
+                function: RefCell::new(None)
             })
         }
     }
@@ -127,7 +212,7 @@ impl<'a> SyntaxContext<'a> {
         let mut steal_registers = Vec::with_capacity(steals.len());
         for (params, registers, is_borrowed) in vec![(borrows, &mut borrow_registers, true), 
                                                      (steals,  &mut steal_registers, false)] {
-            for p in params {
+            for (idx, p) in params.into_iter().enumerate() {
                 if self.locals.contains_key(&p.name) {
                     panic!("Duplicate function parameter names");
                 };
@@ -137,7 +222,11 @@ impl<'a> SyntaxContext<'a> {
                 if !p.is_ref {
                     // Singly owned //
                     let new_var = self.new_variable(p.name.clone(), register, is_borrowed);
-                    self.locals.insert(p.name, new_var);
+                    if is_borrowed {
+                        // May be called indirectly, in which case the caller must pass a function
+                        *new_var.var.function.borrow_mut() = Some(FunctionSource::Param(idx));
+                    }
+                    self.insert_local(p.name, new_var);
 
                 } else if let Some(link) = p.link {
                     let is_interior = is_interior_link(&link);
@@ -147,9 +236,9 @@ impl<'a> SyntaxContext<'a> {
                             // Existing link name //
                             if is_interior {var.interiors.borrow_mut().insert(p.name.clone())}
                             else           {var.exteriors.borrow_mut().insert(p.name.clone())};
-                            self.locals.insert(
+                            self.insert_local(
                                 p.name,
-                                Reference{is_interior, register, is_borrowed, is_global: false, var: Rc::clone(var)}
+                                Reference{is_interior, register, is_borrowed, is_global: false, is_readonly: false, var: Rc::clone(var)}
                             );
                         },
                         None => {
@@ -163,12 +252,13 @@ impl<'a> SyntaxContext<'a> {
                             let var = Rc::new(Variable{
                                 id: self.new_variable_id(),
                                 exteriors: RefCell::new(exteriors),
-                                interiors: RefCell::new(interiors)
+                                interiors: RefCell::new(interiors),
+                                function: RefCell::new(None)
                             });
                             linked.insert(ext_link, Rc::clone(&var));
-                            self.locals.insert(
+                            self.insert_local(
                                 p.name,
-                                Reference{is_interior, register, is_borrowed, is_global: false, var}
+                                Reference{is_interior, register, is_borrowed, is_global: false, is_readonly: false, var}
                             );
                         }
                     }
@@ -177,7 +267,7 @@ impl<'a> SyntaxContext<'a> {
                     // Unbound ref //
                     let varref = self.new_variable(p.name.clone(), register, is_borrowed);
                     varref.var.interiors.borrow_mut().insert(String::from("calling scope"));
-                    self.locals.insert(p.name, varref);
+                    self.insert_local(p.name, varref);
                 }
             }
         }
@@ -222,12 +312,164 @@ impl<'a> SyntaxContext<'a> {
         self.consts.len() - 1
     }
 
+    fn qualified_name(&self, name: &str) -> String {
+        // Names are resolved relative to the namespace of the file being checked, and then out of
+        // any namespace which only aliases the one a file was first imported into
+        let mut qualified = format!("{}{}", self.namespace, name);
+        while let Some((alias, namespace)) = self.namespace_aliases.iter()
+                                                 .find(|(alias, _)| qualified.starts_with(alias.as_str())) {
+            qualified = format!("{}{}", namespace, &qualified[alias.len()..]);
+        }
+        qualified
+    }
+
     fn lookup_function_prototype(&self, name: &str) -> Result<&ST::FunctionPrototype, SyntaxError> {
-        self.functions.get(name).ok_or(
+        self.functions.get(&self.qualified_name(name)).ok_or(
             SyntaxError{line: 0, col: 0, desc: format!("Undefined function \"{}\"", name)}
         )
     }
 
+    // The qualified name of the function a lookup names, if it names a function rather than a
+    // variable. The parser splits dotted names into fields, so names from imports need to be put
+    // back together
+    fn function_value_name(&self, lookup: &PT::LookupNode) -> Option<String> {
+        if self.lookup_variable(&lookup.name).is_ok() {
+            return None;
+        }
+        let fields = lookup.indices.iter().map(|i| i.field_name()).collect::<Option<Vec<_>>>()?;
+        let func_name = std::iter::once(lookup.name.as_str()).chain(fields).collect::<Vec<_>>().join(".");
+        self.lookup_function_prototype(&func_name).ok()?;
+        Some(self.qualified_name(&func_name))
+    }
+
+    fn check_function_value_call(
+        &self,
+        name: &str,
+        shape: CallShape,
+        args: &[PT::LookupNode],
+        line: usize,
+        col: usize
+    ) -> Result<(), SyntaxError> {
+
+        let mut error = SyntaxError{line, col, desc: String::new()};
+        let source = self.lookup_variable(name)?.var.function.borrow().clone();
+        match source {
+            Some(FunctionSource::Known(func_name)) => {
+                let proto = &self.functions[&func_name];
+                if CallShape::of(proto) != shape {
+                    error.desc = format!(
+                        "Calling \"{}\" with parameters {}, but it holds function \"{}\" which takes {}",
+                        name, shape, func_name, CallShape::of(proto)
+                    );
+                    return Err(error);
+                }
+                self.check_borrow_args(&proto.borrow_params, args, line, col)
+            },
+            Some(FunctionSource::Param(idx)) => {
+                // Functions passed to called parameters can only borrow unlinked references
+                let unlinked = Some(ST::ParamLink{is_interior: true, link: None, linked_borrow: None, linked_io: None});
+                self.check_borrow_args(&vec![unlinked; args.len()], args, line, col)?;
+                let mut checks = self.function_checks.borrow_mut();
+                let key = (self.function.to_string(), idx);
+                if let Some(existing) = checks.shapes.get(&key) {
+                    if *existing != shape {
+                        error.desc = format!(
+                            "Calling parameter \"{}\" with parameters {}, but it's elsewhere called with {}",
+                            name, shape, existing
+                        );
+                        return Err(error);
+                    }
+                }
+                checks.shapes.insert(key, shape);
+                Ok(())
+            },
+            None => {
+                error.desc = format!("Calling \"{}\" which doesn't hold a function", name);
+                Err(error)
+            }
+        }
+    }
+
+    fn record_function_args(&self, callee: &str, args: &[PT::LookupNode], line: usize, col: usize) -> Result<(), SyntaxError> {
+        let callee = self.qualified_name(callee);
+        let mut checks = self.function_checks.borrow_mut();
+        for (param, arg) in args.iter().enumerate() {
+            let source = if let Some(name) = self.function_value_name(arg) {
+                Some(FunctionSource::Known(name))
+            } else if arg.indices.is_empty() {
+                self.lookup_variable(&arg.name)?.var.function.borrow().clone()
+            } else {None};
+            checks.args.push(FunctionArg{
+                caller: self.function.to_string(), line, col,
+                name: arg.name.clone(), callee: callee.clone(), param, source
+            });
+        }
+        Ok(())
+    }
+
+    fn check_borrow_args(
+        &self,
+        borrow_params: &[Option<ST::ParamLink>],
+        args: &[PT::LookupNode],
+        line: usize,
+        col: usize
+    ) -> Result<(), SyntaxError> {
+
+        let mut error = SyntaxError{line, col, desc: String::new()};
+        if args.len() != borrow_params.len() {
+            error.desc = format!(
+                "Passing {} borrowed arguments to a function which takes {}",
+                args.len(), borrow_params.len()
+            );
+            return Err(error);
+        }
+
+        let mut used_links: HashMap<Rc<Variable>, Option<String>> = HashMap::new();
+        let mut used_vars: HashMap<String, Rc<Variable>> = HashMap::new();
+
+        for (param, proto_link) in args.iter().zip(borrow_params.iter()) {
+
+            // A function passed by name is a new value, so can't alias anything
+            if self.function_value_name(param).is_some() {
+                continue;
+            }
+            let var = &self.lookup_variable(&param.name).map_err(|e| SyntaxError{line, col, ..e})?.var;
+            let link = proto_link.clone().map(|pl| pl.link).flatten();
+            if let Some(other_link) = used_links.get(var) {
+                if link != *other_link {
+                    error.desc = String::from("Passing incorrectly linked references");
+                    return Err(error);
+            }};
+            used_links.insert(Rc::clone(var), link.clone());
+            if let Some(link) = &link {
+                if let Some(other_var) = used_vars.get(link) {
+                    if *var != *other_var {
+                        error.desc = String::from("Passing incorrectly linked references");
+                        return Err(error);
+                }}
+                used_vars.insert(link.clone(), Rc::clone(var));
+            };
+
+            match proto_link {
+                Some(proto_link) => {
+                    if !proto_link.is_interior && self.lookup_variable(&param.name)?.is_interior {
+                        error.desc = String::from("Passing interior to function marked as exterior");
+                        return Err(error);
+                    }
+                },
+                None => {
+                    if !self.check_singly_owned(&param.name)? {
+                        error.desc = String::from("Call uses non-singly owned variable");
+                        return Err(error);
+                    }
+
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn check_singly_owned(&self, name: &str) -> Result<bool, SyntaxError> {
         let var = &self.lookup_variable(name)?.var;
         
@@ -245,6 +487,30 @@ impl<'a> SyntaxContext<'a> {
             format!("Looking up non-existant variable \"{}\"", name)})
     }
 
+    // Adds a local variable, remembering which register it uses from this statement on for debugging
+    fn insert_local(&mut self, name: String, reference: Reference) {
+        self.symbols.push(interpreter::Symbol{
+            name: name.clone(),
+            register: reference.register,
+            live_from: self.stmt_pos,
+            live_until: None
+        });
+        self.locals.insert(name, reference);
+    }
+
+    fn remove_local(&mut self, name: &str) -> Option<Reference> {
+        let reference = self.locals.remove(name)?;
+        self.end_symbol(name);
+        Some(reference)
+    }
+
+    // Records that a variable's register stops holding it at this statement
+    fn end_symbol(&mut self, name: &str) {
+        if let Some(symbol) = self.symbols.iter_mut().rev().find(|s| s.name == name && s.live_until.is_none()) {
+            symbol.live_until = self.stmt_pos;
+        }
+    }
+
     fn get_free_register(&mut self) -> usize {
         match self.free_registers.pop() {
             Some(r) => r,
@@ -262,10 +528,26 @@ impl<'a> SyntaxContext<'a> {
         };
         let register = self.get_free_register();
         let new_var = self.new_variable(name.to_string(), register, false);
-        self.locals.insert(name.to_string(), new_var);
+        self.insert_local(name.to_string(), new_var);
+        Ok(register)
+    }
+
+    fn create_index_variable(&mut self, name: &str) -> Result<usize, SyntaxError> {
+        let register = self.create_variable(name)?;
+        self.locals.get_mut(name).unwrap().is_readonly = true;
         Ok(register)
     }
 
+    fn remove_index_variable(&mut self, name: &str) {
+        let reference = self.remove_local(name).expect("Removing non-existant index variable");
+        self.free_registers.push(reference.register);
+    }
+
+    fn check_readonly(&self, name: &str) -> Result<bool, SyntaxError> {
+        let reference = self.lookup_variable(name)?;
+        Ok(reference.is_readonly || self.readonly_vars.contains(&reference.var.id))
+    }
+
     pub fn create_ref(&mut self, name: &str, lookup: &PT::LookupNode) -> Result<usize, SyntaxError> {
         if self.locals.contains_key(name) {
             return Err(SyntaxError{line: 0, col: 0, desc: // TODO: can pass line numbers through to here
@@ -278,6 +560,7 @@ impl<'a> SyntaxContext<'a> {
         let var = Rc::clone(&src.var);
         let is_borrowed = false;
         let is_global = false;
+        let is_readonly = src.is_readonly;
 
         let register = if is_interior || src.is_global {
             self.get_free_register()
@@ -291,9 +574,9 @@ impl<'a> SyntaxContext<'a> {
             var.exteriors.borrow_mut().insert(name.to_string());
         }
 
-        self.locals.insert(
+        self.insert_local(
             name.to_string(),
-            Reference{is_interior, register, var, is_borrowed, is_global}
+            Reference{is_interior, register, var, is_borrowed, is_global, is_readonly}
         );
         Ok(register)
     }
@@ -302,7 +585,7 @@ impl<'a> SyntaxContext<'a> {
     pub fn remove_ref(&mut self, name: &str, lookup: &PT::LookupNode) -> Result<usize, SyntaxError> {
         let mut err = SyntaxError{line: 0, col: 0, desc: String::new()};  // TODO: can pass line numbers through to here
 
-        match self.locals.remove(name) {
+        match self.remove_local(name) {
             None => {
                 err.desc = format!("Removing non-existant reference \"{}\"", name);
                 Err(err)
@@ -341,7 +624,11 @@ impl<'a> SyntaxContext<'a> {
     fn remove_variable(&mut self, name: &str) -> Result<usize, SyntaxError> {
         let mut err = SyntaxError{line: 0, col: 0, desc: String::new()};
 
-        match self.locals.remove(name) {
+        if self.check_readonly(name).unwrap_or(false) {
+            err.desc = format!("Uninitialising read-only variable \"{}\"", name);
+            return Err(err);
+        }
+        match self.remove_local(name) {
             None => {
                 err.desc = format!("Uninitialising non-existant variable \"{}\"", name);
                 Err(err)
@@ -389,7 +676,10 @@ impl<'a> SyntaxContext<'a> {
     }
 
     fn exit_block_nocheck(&mut self) {
-        mem::replace(&mut self.locals, self.locals_stack.pop().expect("Failed to pop from locals_stack"));
+        let locals = mem::replace(&mut self.locals, self.locals_stack.pop().expect("Failed to pop from locals_stack"));
+        for name in locals.keys() {
+            self.end_symbol(name);
+        }
     }
 }
 
@@ -443,6 +733,98 @@ impl PT::Expression for PT::UniopNode {
     }
 }
 
+impl PT::Expression for PT::CastNode {
+    fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
+        // Types are "frac", or "u" followed by a bit width from 1 to 64
+        let bits = self.type_name.strip_prefix('u').and_then(|b| b.parse::<u32>().ok());
+        let op = match bits {
+            _ if self.type_name == "frac" => interpreter::Instruction::ToFrac,
+            Some(bits) if (1..=64).contains(&bits) => interpreter::Instruction::ToInt{bits},
+            _ => return Err(SyntaxError{
+                line: self.line, col: self.col,
+                desc: format!("Unknown type \"{}\", expected \"frac\" or \"u1\" to \"u64\"", self.type_name)
+            })
+        };
+        let expr = self.expr.to_syntax_node(ctx)?;
+        let is_mono = expr.is_mono();
+        let used_vars = expr.used_vars().clone();
+        Ok(Box::new(ST::UniopNode{expr, is_mono, used_vars, op}))
+    }
+}
+
+impl PT::Expression for PT::CallExprNode {
+    fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
+        // A variable holding a function is called like the function, which must borrow its
+        // arguments and return a single value to be used in an expression
+        let unusable = |name: &str, proto: &ST::FunctionPrototype| {
+            let problem = if      !proto.steal_params.is_empty()     {"steals parameters"}
+                          else if proto.return_params.len() != 1   {"does not return exactly one value"}
+                          else if proto.return_params[0].is_some() {"returns a reference"}
+                          else                                     {return Ok(())};
+            Err(SyntaxError{
+                line: self.line, col: self.col,
+                desc: format!("Function \"{}\" {}, so can't be called inside an expression", name, problem)
+            })
+        };
+        let is_indirect = ctx.lookup_variable(&self.name).is_ok();
+        let func_idx = if is_indirect {
+            if let Some(FunctionSource::Known(func_name)) = &*ctx.lookup_variable(&self.name)?.var.function.borrow() {
+                unusable(func_name, &ctx.functions[func_name])?;
+            }
+            let shape = CallShape{borrows: self.borrow_args.len(), steals: 0, returns: 1};
+            ctx.check_function_value_call(&self.name, shape, &self.borrow_args, self.line, self.col)?;
+            None
+        } else {
+            let proto = ctx.lookup_function_prototype(&self.name)
+                           .map_err(|e| SyntaxError{line: self.line, col: self.col, ..e})?;
+            unusable(&self.name, proto)?;
+            ctx.check_borrow_args(&proto.borrow_params, &self.borrow_args, self.line, self.col)?;
+            ctx.record_function_args(&self.name, &self.borrow_args, self.line, self.col)?;
+            Some(proto.id)
+        };
+
+        let borrow_args = self.borrow_args.into_iter()
+                                          .map(|a| Box::new(a).to_syntax_node(ctx))
+                                          .collect::<Result<Vec<_>, _>>()?;
+        let callee = match func_idx {
+            Some(idx) => ST::Callee::Function(idx),
+            None => {
+                let lookup = PT::LookupNode{line: self.line, col: self.col, name: self.name, indices: Vec::new()};
+                ST::Callee::Reference(lookup.to_syntax_node_unboxed(ctx)?)
+            }
+        };
+        let is_mono = borrow_args.iter().any(|a| a.is_mono());
+        let mut used_vars: HashSet<isize> = borrow_args.iter().map(|a| a.used_vars())
+                                                       .flat_map(|it| it.clone())
+                                                       .collect();
+        if let ST::Callee::Reference(lookup) = &callee {
+            used_vars.extend(lookup.used_vars.iter().cloned());
+        }
+
+        Ok(Box::new(ST::CallExprNode{callee, borrow_args, is_mono, used_vars}))
+    }
+}
+
+impl PT::Expression for PT::TernaryNode {
+    fn get_src_pos(&self) -> (usize, usize) { self.condition.get_src_pos() }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
+        let condition = self.condition.to_syntax_node(ctx)?;
+        let if_expr = self.if_expr.to_syntax_node(ctx)?;
+        let else_expr = self.else_expr.to_syntax_node(ctx)?;
+        let is_mono = condition.is_mono() || if_expr.is_mono() || else_expr.is_mono();
+        let used_vars = condition.used_vars().iter()
+                        .chain(if_expr.used_vars().iter())
+                        .chain(else_expr.used_vars().iter())
+                        .cloned().collect();
+        Ok(Box::new(ST::TernaryNode{condition, if_expr, else_expr, is_mono, used_vars}))
+    }
+}
+
 impl PT::Expression for PT::ArrayLiteralNode {
     fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
 
@@ -458,6 +840,56 @@ impl PT::Expression for PT::ArrayLiteralNode {
     }
 }
 
+impl PT::Expression for PT::RecordLiteralNode {
+    fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
+        let mut names = HashSet::new();
+        let mut fields = Vec::with_capacity(self.fields.len());
+        for (name, value) in self.fields.into_iter() {
+            if !names.insert(name.clone()) {
+                return Err(SyntaxError{
+                    line: self.line, col: self.col,
+                    desc: format!("Duplicate field \"{}\" in record literal", name)
+                });
+            }
+            let name_idx = ctx.add_const(interpreter::Variable::Str(name));
+            fields.push((name_idx, value.to_syntax_node(ctx)?));
+        }
+        let is_mono = fields.iter().any(|(_, x)| x.is_mono());
+        let used_vars = fields.iter().map(|(_, x)| x.used_vars())
+                                     .flat_map(|it| it.clone())
+                                     .collect();
+        Ok(Box::new(ST::RecordLiteralNode{fields, is_mono, used_vars}))
+    }
+}
+
+impl PT::Expression for PT::MapLiteralNode {
+    fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
+        let entries = self.entries.into_iter()
+                                  .map(|(k, v)| Ok((k.to_syntax_node(ctx)?, v.to_syntax_node(ctx)?)))
+                                  .collect::<Result<Vec<_>, SyntaxError>>()?;
+        let is_mono = entries.iter().any(|(k, v)| k.is_mono() || v.is_mono());
+        let used_vars = entries.iter().flat_map(|(k, v)| k.used_vars().iter().chain(v.used_vars()))
+                                      .cloned()
+                                      .collect();
+        Ok(Box::new(ST::MapLiteralNode{entries, is_mono, used_vars}))
+    }
+}
+
+impl PT::Expression for PT::FieldNode {
+    fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
+    fn field_name(&self) -> Option<&str> { Some(&self.name) }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
+        // Fields are looked up by name, so a field in a lookup path is just its name as a constant
+        let const_idx = ctx.add_const(interpreter::Variable::Str(self.name));
+        Ok(Box::new(ST::StringNode{const_idx, used_vars: HashSet::new()}))
+    }
+}
+
 impl PT::Expression for PT::ArrayRepeatNode {
     fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
 
@@ -476,6 +908,12 @@ impl PT::Expression for PT::LookupNode {
     fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
 
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
+        // A name which isn't a variable may name a function, giving a function value
+        if let Some(name) = ctx.function_value_name(&self) {
+            let id = ctx.functions[&name].id;
+            let const_idx = ctx.add_const(interpreter::Variable::Func(id, Rc::from(name.as_str())));
+            return Ok(Box::new(ST::FunctionRefNode{const_idx, name, used_vars: HashSet::new()}));
+        }
         Ok(Box::new(self.to_syntax_node_unboxed(ctx)?))
     }
 }
@@ -514,17 +952,23 @@ impl PT::Statement for PT::PrintNode {
         let newline = self.newline;
         let is_mono = items.iter().any(|i| i.is_mono());
 
-        Ok(Box::new(ST::PrintNode{items, newline, is_mono}))
+        Ok(Box::new(ST::PrintNode{line: self.line, col: self.col, items, newline, is_mono}))
     }
 }
 
 impl PT::Statement for PT::LetUnletNode {
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        ctx.stmt_pos = Some((self.line, self.col));
         let is_unlet = self.is_unlet;
-        let register = if self.is_unlet {ctx.remove_variable(&self.name)?}
-                       else             {ctx.create_variable(&self.name)?};
+        let register = if self.is_unlet {ctx.remove_variable(&self.name)}
+                       else             {ctx.create_variable(&self.name)};
+        let register = register.map_err(|e| SyntaxError{line: self.line, col: self.col, ..e})?;
         let rhs = self.rhs.to_syntax_node(ctx)?;
         let is_mono = self.name.starts_with(".");
+        if let (false, Some(func_name)) = (is_unlet, rhs.function_name()) {
+            let source = FunctionSource::Known(func_name.to_string());
+            *ctx.lookup_variable(&self.name)?.var.function.borrow_mut() = Some(source);
+        }
 
         if !is_mono && rhs.is_mono() {
             let verb = if is_unlet {"Uninitialising"} else {"Initialising"};
@@ -534,12 +978,13 @@ impl PT::Statement for PT::LetUnletNode {
             });
         }
 
-        Ok(Box::new(ST::LetUnletNode{is_unlet, register, rhs, is_mono}))
+        Ok(Box::new(ST::LetUnletNode{line: self.line, col: self.col, is_unlet, register, rhs, is_mono}))
     }
 }
 
 impl PT::Statement for PT::RefUnrefNode {
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        ctx.stmt_pos = Some((self.line, self.col));
         let is_unref = self.is_unref;
         let register = if self.is_unref {ctx.remove_ref(&self.name, &self.rhs)?}
                        else             {ctx.create_ref(&self.name, &self.rhs)?};
@@ -557,7 +1002,7 @@ impl PT::Statement for PT::RefUnrefNode {
             });
         }
 
-        Ok(Box::new(ST::RefUnrefNode{is_unref, register, rhs, is_mono}))
+        Ok(Box::new(ST::RefUnrefNode{line: self.line, col: self.col, is_unref, register, rhs, is_mono}))
     }
 }
 
@@ -574,6 +1019,11 @@ impl PT::Statement for PT::ModopNode {
                 "Modifying variable \"{}\" using mono information", varname
             )});
         }
+        if ctx.check_readonly(&varname)? {
+            return Err(SyntaxError{line, col, desc: format!(
+                "Modifying read-only variable \"{}\"", varname
+            )});
+        }
         if rhs.used_vars().contains(&lookup.var_id) {
             return Err(SyntaxError{line, col, desc: format!(
                 "Self-modification of variable \"{}\"", varname
@@ -585,22 +1035,27 @@ impl PT::Statement for PT::ModopNode {
             )});
         }
 
-        Ok(Box::new(ST::ModopNode{lookup, rhs, is_mono, op: self.op}))
+        Ok(Box::new(ST::ModopNode{line, col, lookup, rhs, is_mono, op: self.op}))
     }
 }
 
 impl PT::Statement for PT::PushPullNode {
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
 
+        ctx.stmt_pos = Some((self.line, self.col));
         let lookup_name = self.lookup.name.clone();
-        let register = if self.is_push {ctx.remove_variable(&self.name)?}
-                       else            {ctx.create_variable(&self.name)?};
+        let register = if self.is_push {ctx.remove_variable(&self.name)}
+                       else            {ctx.create_variable(&self.name)};
+        let register = register.map_err(|e| SyntaxError{line: self.line, col: self.col, ..e})?;
         let lookup = self.lookup.to_syntax_node_unboxed(ctx)?;
         let is_mono = self.name.starts_with(".");
 
 
         let mut error = SyntaxError{line: self.line, col: self.col, desc: String::new()};
-        if !ctx.check_ref_is_resizable(&lookup_name)? {
+        if ctx.check_readonly(&lookup_name)? {
+            error.desc = format!("Resizing read-only variable \"{}\"", lookup_name);
+            return Err(error);
+        } else if !ctx.check_ref_is_resizable(&lookup_name)? {
             error.desc = format!("Resizing \"{}\" when other references to its interior exist", lookup_name);
             return Err(error);
         } else if is_mono != lookup.var_is_mono {
@@ -611,7 +1066,7 @@ impl PT::Statement for PT::PushPullNode {
             return Err(error);
         }
 
-        Ok(Box::new(ST::PushPullNode{register, lookup, is_mono, is_push: self.is_push}))
+        Ok(Box::new(ST::PushPullNode{line: self.line, col: self.col, register, lookup, is_mono, is_push: self.is_push}))
     }
 }
 
@@ -644,7 +1099,7 @@ impl PT::Statement for PT::IfNode {
                 "Backward condition in If statement is mono")})
         }
 
-        Ok(Box::new(ST::IfNode{fwd_expr, if_stmts, else_stmts, bkwd_expr, is_mono}))
+        Ok(Box::new(ST::IfNode{line: self.line, col: self.col, fwd_expr, if_stmts, else_stmts, bkwd_expr, is_mono}))
     }
 }
 
@@ -680,30 +1135,65 @@ impl PT::Statement for PT::WhileNode {
             }
         }
 
-        Ok(Box::new(ST::WhileNode{fwd_expr, stmts, bkwd_expr, is_mono}))
+        Ok(Box::new(ST::WhileNode{line: self.line, col: self.col, fwd_expr, stmts, bkwd_expr, is_mono}))
     }
 }
 
 impl PT::Statement for PT::ForNode {
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
 
+        ctx.stmt_pos = Some((self.line, self.col));
         let mut zero_lookup = self.iterator.clone();
         zero_lookup.indices.push(Box::new(PT::FractionNode{
             value: interpreter::Fraction::zero(),
             line: 0, col: 0
         }));
         
-        let register = ctx.create_ref(&self.iter_var, &zero_lookup)?;
+        // Looping over the keys of a map binds read-only copies of them rather than references
+        // to the items, and stops the map being resized instead
+        let register = if self.over_keys {ctx.create_index_variable(&self.iter_var)}
+                       else              {ctx.create_ref(&self.iter_var, &zero_lookup)}
+                       .map_err(|e| SyntaxError{line: self.line, col: self.col, ..e})?;
+        let keys_anchor = format!("keys loop at {}:{}", self.line, self.col);
+        let iterator_var = Rc::clone(&ctx.lookup_variable(&self.iterator.name)
+                                         .map_err(|e| SyntaxError{line: self.line, col: self.col, ..e})?.var);
+        if self.over_keys {
+            iterator_var.interiors.borrow_mut().insert(keys_anchor.clone());
+        }
+        let index_register = match &self.index_var {
+            Some(name) => Some(ctx.create_index_variable(name)?),
+            None => None
+        };
         let (iter_line, iter_col) = (self.iterator.line, self.iterator.col);
         let iterator = self.iterator.to_syntax_node_unboxed(ctx)?;
+
+        // The iterator is looked up again at the other end of the loop when running backwards,
+        // so the variables indexing it must be left alone by the body
+        let index_vars: Vec<_> = iterator.index_used_vars.iter()
+                                         .filter(|id| ctx.readonly_vars.insert(**id))
+                                         .cloned()
+                                         .collect();
         ctx.enter_block();
         let stmts = self.stmts.into_iter()
                               .map(|s| s.to_syntax_node(ctx))
-                              .collect::<Result<Vec<_>, _>>()?;
+                              .collect::<Result<Vec<_>, _>>();
+        for id in index_vars {
+            ctx.readonly_vars.remove(&id);
+        }
+        iterator_var.interiors.borrow_mut().remove(&keys_anchor);
+        let stmts = stmts?;
         ctx.exit_block()?;
         let is_mono = self.iter_var.starts_with(".");
 
-        ctx.remove_ref(&self.iter_var, &zero_lookup)?;
+        ctx.stmt_pos = Some((self.end_line, self.end_col));
+        if let Some(name) = &self.index_var {
+            ctx.remove_index_variable(name);
+        }
+        if self.over_keys {
+            ctx.remove_index_variable(&self.iter_var);
+        } else {
+            ctx.remove_ref(&self.iter_var, &zero_lookup)?;
+        }
         
         if is_mono {
             if !iterator.var_is_mono {
@@ -723,16 +1213,17 @@ impl PT::Statement for PT::ForNode {
                     self.iter_var
                 )});
         }
+        if let Some(name) = &self.index_var {
+            if name.starts_with(".") != is_mono {
+                return Err(SyntaxError{
+                    line: iter_line, col: iter_col, desc: format!(
+                        "Index variable \"{}\" has different mono-ness to iteration variable \"{}\"",
+                        name, self.iter_var
+                    )});
+            }
+        }
 
-        /* 
-        TODO: disallow modification of iterator indices in for-loop body e.g. 
-            for (_ in array[i]) {
-                i += 1;
-            };
-        is not invertible
-        */
-
-        Ok(Box::new(ST::ForNode{register, iterator, stmts, is_mono}))
+        Ok(Box::new(ST::ForNode{line: self.line, col: self.col, register, index_register, over_keys: self.over_keys, iterator, stmts, is_mono}))
     }
 }
 
@@ -748,15 +1239,63 @@ impl PT::Statement for PT::DoYieldNode {
                                           .map(|s| s.to_syntax_node(ctx))
                                           .collect::<Result<Vec<_>, _>>()?;
         ctx.exit_block()?;
+        ctx.stmt_pos = Some((self.end_line, self.end_col));
         ctx.exit_block_nocheck();  // The undo WILL free locals properly
 
-        Ok(Box::new(ST::DoYieldNode{do_stmts, yield_stmts}))
+        Ok(Box::new(ST::DoYieldNode{line: self.line, col: self.col, do_stmts, yield_stmts}))
+    }
+}
+
+impl PT::Statement for PT::LocalNode {
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+
+        let PT::LocalNode{line, col, end_line, end_col, name, init, stmts, exit_name, exit} = *self;
+        let mut error = SyntaxError{line, col, desc: String::new()};
+        if exit_name != name {
+            error.desc = format!(
+                "Local variable \"{}\" is delocalised using the name \"{}\"", name, exit_name);
+            return Err(error);
+        }
+
+        let init = init.to_syntax_node(ctx)?;
+        ctx.enter_block();
+        ctx.stmt_pos = Some((line, col));
+        let register = ctx.create_variable(&name)?;
+        ctx.enter_block();
+        let stmts = stmts.into_iter()
+                         .map(|s| s.to_syntax_node(ctx))
+                         .collect::<Result<Vec<_>, _>>()?;
+        ctx.stmt_pos = Some((end_line, end_col));
+        let scope_check = ctx.exit_block().and_then(|_| ctx.remove_variable(&name));
+        if let Err(SyntaxError{desc, ..}) = scope_check {
+            error.desc = format!("Local block for \"{}\" leaks a reference: {}", name, desc);
+            return Err(error);
+        }
+        ctx.exit_block_nocheck();
+        let exit = exit.to_syntax_node(ctx)?;
+
+        let var_is_mono = name.starts_with(".");
+        let is_mono = var_is_mono && stmts.iter().all(|s| s.is_mono());
+        if !var_is_mono && (init.is_mono() || exit.is_mono()) {
+            error.desc = format!("Local variable \"{}\" is initialised or uninitialised using mono information", name);
+            return Err(error);
+        }
+
+        Ok(Box::new(ST::LocalNode{line, col, register, init, stmts, exit, var_is_mono, is_mono}))
     }
 }
 
 impl PT::Statement for PT::CatchNode {
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
-        Ok(Box::new(ST::CatchNode{expr: self.expr.to_syntax_node(ctx)?}))
+        Ok(Box::new(ST::CatchNode{line: self.line, col: self.col, expr: self.expr.to_syntax_node(ctx)?}))
+    }
+}
+
+impl PT::Statement for PT::AssertNode {
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        let expr = self.expr.to_syntax_node(ctx)?;
+        let is_mono = expr.is_mono();
+        Ok(Box::new(ST::AssertNode{line: self.line, col: self.col, expr, is_mono}))
     }
 }
 
@@ -764,6 +1303,7 @@ impl PT::Statement for PT::CatchNode {
 impl PT::Statement for PT::CallNode {
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
 
+        ctx.stmt_pos = Some((self.line, self.col));
         /* 
         TODO:
             ✓ Check singly owned params are singly owned
@@ -777,57 +1317,52 @@ impl PT::Statement for PT::CallNode {
 
         let mut error = SyntaxError{line: self.line, col: self.col, desc: String::new()};
 
-        let proto = ctx.lookup_function_prototype(&self.name)?;
-        let func_idx = proto.id;
-        let mut used_links: HashMap<Rc<Variable>, Option<String>> = HashMap::new();
-        let mut used_vars: HashMap<String, Rc<Variable>> = HashMap::new();
-
-        for (param, proto_link) in self.borrow_args.iter().zip(proto.borrow_params.iter()) {
-
-            let var = &ctx.lookup_variable(&param.name)?.var;
-            let link = proto_link.clone().map(|pl| pl.link).flatten();
-            if let Some(other_link) = used_links.get(var) {
-                if link != *other_link {
-                    error.desc = String::from("Passing incorrectly linked references");
-                    return Err(error);
-            }};
-            used_links.insert(Rc::clone(var), link.clone());
-            if let Some(link) = &link {
-                if let Some(other_var) = used_vars.get(link) {
-                    if *var != *other_var {
-                        error.desc = String::from("Passing incorrectly linked references");
-                        return Err(error);
-                }}
-                used_vars.insert(link.clone(), Rc::clone(var));
-                // done here?
-            };
-
-
-            match proto_link {
-                Some(proto_link) => {
-                    if !proto_link.is_interior && ctx.lookup_variable(&param.name)?.is_interior {
-                        error.desc = String::from("Passing interior to function marked as exterior");
-                        return Err(error);
-                    }
-                },
-                None => {
-                    if !ctx.check_singly_owned(&param.name)? {
-                        error.desc = String::from("Call uses non-singly owned variable");
-                        return Err(error);
-                    }
-
-                }
+        for param in self.borrow_args.iter() {
+            if ctx.function_value_name(param).is_some() {
+                continue;
+            }
+            if ctx.check_readonly(&param.name).map_err(|e| SyntaxError{line: self.line, col: self.col, ..e})? {
+                error.desc = format!("Passing read-only variable \"{}\" to a function", param.name);
+                return Err(error);
             }
         }
 
+        // Calling a variable rather than a function name calls the function value it holds
+        let is_indirect = ctx.lookup_variable(&self.name).is_ok();
+        let func_idx = if is_indirect {
+            let (inputs, outputs) = (self.stolen_args.len(), self.return_args.len());
+            let (steals, returns) = if self.is_uncall {(outputs, inputs)} else {(inputs, outputs)};
+            let shape = CallShape{borrows: self.borrow_args.len(), steals, returns};
+            ctx.check_function_value_call(&self.name, shape, &self.borrow_args, self.line, self.col)?;
+            None
+        } else {
+            let proto = ctx.lookup_function_prototype(&self.name)
+                           .map_err(|e| SyntaxError{line: self.line, col: self.col, ..e})?;
+            ctx.check_borrow_args(&proto.borrow_params, &self.borrow_args, self.line, self.col)?;
+            let func_idx = proto.id;
+            ctx.record_function_args(&self.name, &self.borrow_args, self.line, self.col)?;
+            Some(func_idx)
+        };
+
         let mut stolen_args = Vec::with_capacity(self.stolen_args.len());
         for arg in self.stolen_args.into_iter() {
+            if ctx.check_readonly(&arg)? {
+                error.desc = format!("Passing read-only variable \"{}\" to a function", arg);
+                return Err(error);
+            }
             stolen_args.push(ctx.lookup_variable(&arg)?.register);
-            ctx.locals.remove(&arg);
+            ctx.remove_local(&arg);
         }
         let borrow_args = self.borrow_args.into_iter()
-                                          .map(|a| a.to_syntax_node_unboxed(ctx))
+                                          .map(|a| Box::new(a).to_syntax_node(ctx))
                                           .collect::<Result<Vec<_>, _>>()?;
+        let callee = match func_idx {
+            Some(idx) => ST::Callee::Function(idx),
+            None => {
+                let lookup = PT::LookupNode{line: self.line, col: self.col, name: self.name, indices: Vec::new()};
+                ST::Callee::Reference(lookup.to_syntax_node_unboxed(ctx)?)
+            }
+        };
         let mut return_args = Vec::with_capacity(self.return_args.len());
         for arg in self.return_args.into_iter() {
             return_args.push(ctx.create_variable(&arg)?);
@@ -837,8 +1372,9 @@ impl PT::Statement for PT::CallNode {
         let is_mono = false;
 
         Ok(Box::new(ST::CallNode{
+            line: self.line, col: self.col,
             is_uncall: self.is_uncall,
-            func_idx, borrow_args, stolen_args, return_args, is_mono
+            callee, borrow_args, stolen_args, return_args, is_mono
         }))
     }
 }
@@ -847,22 +1383,29 @@ impl PT::FunctionNode {
     fn to_syntax_node(
         self,
         func_lookup: &HashMap<String, ST::FunctionPrototype>,
+        namespace_aliases: &HashMap<String, String>,
         global_vars: &HashMap<String, Reference>,
+        function_checks: &RefCell<FunctionValueChecks>
     ) -> Result<ST::FunctionNode, SyntaxError> {
-        let (syntax_node, _) = self.to_syntax_node_and_locals(func_lookup, global_vars)?;
+        let (syntax_node, _) = self.to_syntax_node_and_locals(func_lookup, namespace_aliases, global_vars, function_checks)?;
         Ok(syntax_node)
     }
 
     fn to_syntax_node_and_locals(
         self,
         func_lookup: &HashMap<String, ST::FunctionPrototype>,
-        global_vars: &HashMap<String, Reference>
+        namespace_aliases: &HashMap<String, String>,
+        global_vars: &HashMap<String, Reference>,
+        function_checks: &RefCell<FunctionValueChecks>
     ) -> Result<
         (ST::FunctionNode, HashMap<String, Reference>),
         SyntaxError
     > {
 
-        let mut ctx = SyntaxContext::new(func_lookup, global_vars);
+        let namespace = self.namespace;
+        let name = self.name;
+        let file = self.file;
+        let mut ctx = SyntaxContext::new(func_lookup, namespace_aliases, global_vars, &namespace, &name, function_checks);
         let (link_set, borrow_registers, steal_registers) = ctx.init_func(
             self.owned_links, self.borrow_params, self.steal_params);
         let stmts = self.stmts.into_iter()
@@ -871,9 +1414,10 @@ impl PT::FunctionNode {
         let return_registers = ctx.end_func(link_set, self.return_params);
 
         let function_node = ST::FunctionNode{
-            stmts, borrow_registers, steal_registers, return_registers,
+            name: name.clone(), file, stmts, borrow_registers, steal_registers, return_registers,
             consts: ctx.consts,
-            num_registers: ctx.num_registers
+            num_registers: ctx.num_registers,
+            symbols: ctx.symbols
         };
 
         Ok((function_node, ctx.locals))
@@ -968,7 +1512,68 @@ impl ST::FunctionPrototype {
     }
 }
 
-pub fn check_syntax(module: PT::Module) -> Result<ST::Module, SyntaxError> {
+impl FunctionValueChecks {
+    fn resolve(
+        mut self,
+        prototypes: &HashMap<String, ST::FunctionPrototype>,
+        files: &HashMap<String, String>
+    ) -> Result<(), SourceError> {
+        // Passing a parameter on to a function which calls it means the parameter is called too,
+        // so keep propagating the required shapes until nothing changes
+        loop {
+            let mut changed = false;
+            for arg in self.args.iter() {
+                let required = match self.shapes.get(&(arg.callee.clone(), arg.param)) {
+                    Some(shape) => shape.clone(),
+                    None => continue
+                };
+                let error = |desc| SyntaxError{line: arg.line, col: arg.col, desc}.in_file(files[&arg.caller].clone());
+                match &arg.source {
+                    Some(FunctionSource::Known(name)) => {
+                        let proto = &prototypes[name];
+                        if CallShape::of(proto) != required {
+                            return Err(error(format!(
+                                "Passing function \"{}\" which takes {} to \"{}\", which calls it with {}",
+                                name, CallShape::of(proto), arg.callee, required
+                            )));
+                        }
+                        if !proto.borrow_params.iter().all(|p| matches!(p, Some(ST::ParamLink{link: None, ..}))) {
+                            return Err(error(format!(
+                                "Passing function \"{}\" to \"{}\", which calls it, but it doesn't borrow only unlinked references",
+                                name, arg.callee
+                            )));
+                        }
+                    },
+                    Some(FunctionSource::Param(idx)) => {
+                        let key = (arg.caller.clone(), *idx);
+                        match self.shapes.get(&key) {
+                            Some(shape) if *shape != required => {
+                                return Err(error(format!(
+                                    "Passing \"{}\" to \"{}\", which calls it with {}, but it's elsewhere called with {}",
+                                    arg.name, arg.callee, required, shape
+                                )));
+                            },
+                            Some(_) => {},
+                            None => {
+                                self.shapes.insert(key, required);
+                                changed = true;
+                            }
+                        }
+                    },
+                    None => {
+                        return Err(error(format!(
+                            "Passing \"{}\" to \"{}\", which calls it, but it doesn't hold a function",
+                            arg.name, arg.callee
+                        )));
+                    }
+                }
+            }
+            if !changed {return Ok(())}
+        }
+    }
+}
+
+pub fn check_syntax(module: PT::Module) -> Result<ST::Module, SourceError> {
     // Collect the properties of all the module functions
     let mut func_prototypes = HashMap::new();
     for f in module.functions.iter() {
@@ -980,9 +1585,17 @@ pub fn check_syntax(module: PT::Module) -> Result<ST::Module, SyntaxError> {
         }
     }
 
+    let function_checks = RefCell::new(FunctionValueChecks::default());
+    let files = module.functions.iter()
+                                .chain(std::iter::once(&module.global_func))
+                                .map(|f| (f.name.clone(), f.file.clone()))
+                                .collect::<HashMap<_, _>>();
+
     // Check the syntax of the global scope pseudo function, and convert the variable into globals
-    let (global_func, mut global_refs) 
-        = module.global_func.to_syntax_node_and_locals(&func_prototypes, &HashMap::new())?;
+    let global_file = module.global_func.file.clone();
+    let (global_func, mut global_refs) = module.global_func
+        .to_syntax_node_and_locals(&func_prototypes, &module.namespace_aliases, &HashMap::new(), &function_checks)
+        .map_err(|e| e.in_file(global_file.clone()))?;
     let mut global_vars: HashMap<isize, Rc<Variable>> = HashMap::new();
     for (_, reference) in global_refs.iter_mut() {
         reference.is_global = true;
@@ -993,7 +1606,8 @@ pub fn check_syntax(module: PT::Module) -> Result<ST::Module, SyntaxError> {
                 let var = Rc::new(Variable{
                     id: -reference.var.id,  // Negative id for globals
                     interiors: RefCell::new(reference.var.interiors.borrow().clone()),
-                    exteriors: RefCell::new(reference.var.exteriors.borrow().clone())
+                    exteriors: RefCell::new(reference.var.exteriors.borrow().clone()),
+                    function: RefCell::new(reference.var.function.borrow().clone())
                 });
                 global_vars.insert(reference.var.id, Rc::clone(&var));
                 var
@@ -1009,8 +1623,14 @@ pub fn check_syntax(module: PT::Module) -> Result<ST::Module, SyntaxError> {
     let mut functions = Vec::with_capacity(module.functions.len());
     for (i, f) in module.functions.into_iter().enumerate() {
         if f.name == "main" {main_idx = Some(i)}
-        functions.push(f.to_syntax_node(&func_prototypes, &global_refs)?);
+        let file = f.file.clone();
+        functions.push(f.to_syntax_node(&func_prototypes, &module.namespace_aliases, &global_refs, &function_checks).map_err(|e| e.in_file(file))?);
     }
+    function_checks.into_inner().resolve(&func_prototypes, &files)?;
+    let main_idx = main_idx.ok_or_else(|| SourceError{
+        kind: "SyntaxError", file: global_file, line: 0, col: 0,
+        desc: String::from("No main function defined")
+    })?;
 
     Ok(ST::Module{functions, main_idx, global_func})
 }
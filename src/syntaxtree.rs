@@ -10,6 +10,12 @@ pub trait Expression: Debug {
     fn is_mono(&self) -> bool;
     fn used_vars(&self) -> &HashSet<isize>;
     fn compile(&self) -> Vec<interpreter::Instruction>;
+
+    // Code for a value which will be modified or referenced rather than just read
+    fn compile_ref(&self) -> Vec<interpreter::Instruction> {self.compile()}
+
+    // The (qualified) name of the function this expression refers to, if it's a function value
+    fn function_name(&self) -> Option<&str> {None}
 }
 
 pub type ExpressionNode = Box<dyn Expression>;
@@ -26,6 +32,13 @@ pub struct StringNode {
     pub used_vars: HashSet<isize>
 }
 
+#[derive(Clone, Debug)]
+pub struct FunctionRefNode {
+    pub const_idx: usize,
+    pub name: String,
+    pub used_vars: HashSet<isize>
+}
+
 #[derive(Debug)]
 pub struct ArrayLiteralNode {
     pub items: Vec<ExpressionNode>,
@@ -33,6 +46,20 @@ pub struct ArrayLiteralNode {
     pub used_vars: HashSet<isize>
 }
 
+#[derive(Debug)]
+pub struct RecordLiteralNode {
+    pub fields: Vec<(usize, ExpressionNode)>,  // (Const index of field name, value)
+    pub is_mono: bool,
+    pub used_vars: HashSet<isize>
+}
+
+#[derive(Debug)]
+pub struct MapLiteralNode {
+    pub entries: Vec<(ExpressionNode, ExpressionNode)>,  // (Key, value)
+    pub is_mono: bool,
+    pub used_vars: HashSet<isize>
+}
+
 #[derive(Debug)]
 pub struct ArrayRepeatNode {
     pub item: ExpressionNode,
@@ -70,17 +97,48 @@ pub struct UniopNode {
     pub used_vars: HashSet<isize>
 }
 
+#[derive(Debug)]
+pub struct CallExprNode {
+    pub callee: Callee,
+    pub borrow_args: Vec<ExpressionNode>,
+    pub is_mono: bool,
+    pub used_vars: HashSet<isize>
+}
+
+#[derive(Debug)]
+pub struct TernaryNode {
+    pub condition: ExpressionNode,
+    pub if_expr: ExpressionNode,
+    pub else_expr: ExpressionNode,
+    pub is_mono: bool,
+    pub used_vars: HashSet<isize>
+}
+
 
 
 pub trait Statement: Debug {
     fn is_mono(&self) -> bool;
-    fn compile(&self) -> compiler::Code;
+    fn get_src_pos(&self) -> (usize, usize);
+    fn compile(&self, options: &compiler::Options) -> compiler::Code;
+
+    // Compiles, attributing any instructions not already attributed to a nested statement to this
+    // one, and marking the point before it for the debugger
+    fn compile_with_src_pos(&self, options: &compiler::Options) -> compiler::Code {
+        let mut code = self.compile(options);
+        if options.debug {
+            code = code.mark_statement(self.get_src_pos());
+        }
+        code.set_src_pos(self.get_src_pos());
+        code
+    }
 }
 
 pub type StatementNode = Box<dyn Statement>;
 
 #[derive(Debug)]
 pub struct PrintNode {
+    pub line: usize,
+    pub col: usize,
     pub items: Vec<ExpressionNode>,
     pub newline: bool,
     pub is_mono: bool,
@@ -88,6 +146,8 @@ pub struct PrintNode {
 
 #[derive(Debug)]
 pub struct LetUnletNode {
+    pub line: usize,
+    pub col: usize,
     pub is_unlet: bool,
     pub register: usize,
     pub rhs: ExpressionNode,
@@ -96,6 +156,8 @@ pub struct LetUnletNode {
 
 #[derive(Debug)]
 pub struct RefUnrefNode {
+    pub line: usize,
+    pub col: usize,
     pub is_unref: bool,
     pub register: usize,
     pub rhs: LookupNode,
@@ -104,6 +166,8 @@ pub struct RefUnrefNode {
 
 #[derive(Debug)]
 pub struct ModopNode {
+    pub line: usize,
+    pub col: usize,
     pub lookup: LookupNode,
     pub op: interpreter::Instruction,
     pub rhs: ExpressionNode,
@@ -112,6 +176,8 @@ pub struct ModopNode {
 
 #[derive(Debug)]
 pub struct PushPullNode {
+    pub line: usize,
+    pub col: usize,
     pub is_push: bool,
     pub register: usize,
     pub lookup: LookupNode,
@@ -120,6 +186,8 @@ pub struct PushPullNode {
 
 #[derive(Debug)]
 pub struct IfNode {
+    pub line: usize,
+    pub col: usize,
     pub fwd_expr: ExpressionNode,
     pub if_stmts: Vec<StatementNode>,
     pub else_stmts: Vec<StatementNode>,
@@ -129,6 +197,8 @@ pub struct IfNode {
 
 #[derive(Debug)]
 pub struct WhileNode {
+    pub line: usize,
+    pub col: usize,
     pub fwd_expr: ExpressionNode,
     pub stmts: Vec<StatementNode>,
     pub bkwd_expr: Option<ExpressionNode>,
@@ -137,28 +207,64 @@ pub struct WhileNode {
 
 #[derive(Debug)]
 pub struct ForNode {
+    pub line: usize,
+    pub col: usize,
     pub register: usize,
+    pub index_register: Option<usize>,
+    pub over_keys: bool,
     pub iterator: LookupNode,
     pub stmts: Vec<StatementNode>,
     pub is_mono: bool
 }
 
+#[derive(Debug)]
+pub struct LocalNode {
+    pub line: usize,
+    pub col: usize,
+    pub register: usize,
+    pub init: ExpressionNode,
+    pub stmts: Vec<StatementNode>,
+    pub exit: ExpressionNode,
+    pub var_is_mono: bool,
+    pub is_mono: bool
+}
+
 #[derive(Debug)]
 pub struct DoYieldNode {
+    pub line: usize,
+    pub col: usize,
     pub do_stmts: Vec<StatementNode>,
     pub yield_stmts: Vec<StatementNode>
 }
 
+#[derive(Debug)]
+pub struct AssertNode {
+    pub line: usize,
+    pub col: usize,
+    pub expr: ExpressionNode,
+    pub is_mono: bool
+}
+
 #[derive(Debug)]
 pub struct CatchNode {
+    pub line: usize,
+    pub col: usize,
     pub expr: ExpressionNode
 }
 
+#[derive(Debug)]
+pub enum Callee {
+    Function(usize),
+    Reference(LookupNode)
+}
+
 #[derive(Debug)]
 pub struct CallNode {
+    pub line: usize,
+    pub col: usize,
     pub is_uncall: bool,
-    pub func_idx: usize,
-    pub borrow_args: Vec<LookupNode>,
+    pub callee: Callee,
+    pub borrow_args: Vec<ExpressionNode>,
     pub stolen_args: Vec<usize>,
     pub return_args: Vec<usize>,
     pub is_mono: bool
@@ -184,9 +290,12 @@ pub struct FunctionPrototype {
 
 #[derive(Debug)]
 pub struct FunctionNode {
+    pub name: String,
+    pub file: String,
     pub stmts: Vec<StatementNode>,
     pub consts: Vec<interpreter::Variable>,
     pub num_registers: usize,
+    pub symbols: Vec<interpreter::Symbol>,
 
     pub borrow_registers: Vec<usize>,
     pub steal_registers: Vec<usize>,
@@ -196,6 +305,6 @@ pub struct FunctionNode {
 #[derive(Debug)]
 pub struct Module {
     pub functions: Vec<FunctionNode>,
-    pub main_idx: Option<usize>,
+    pub main_idx: usize,
     pub global_func: FunctionNode
 }
\ No newline at end of file
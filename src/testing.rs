@@ -0,0 +1,44 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::compiler;
+use crate::interpreter::Module;
+use crate::resolver;
+use crate::syntaxchecker::check_syntax;
+
+
+// Tests in this process, and in other checkouts running at the same time, each get their own file
+static NEXT_PROGRAM: AtomicUsize = AtomicUsize::new(0);
+
+// A program written to a temporary file for a test, which is deleted again when dropped
+pub struct TempProgram {
+    path: PathBuf
+}
+
+impl TempProgram {
+    pub fn new(source: &str) -> TempProgram {
+        let name = format!("monoxide_test_{}_{}.mx", process::id(), NEXT_PROGRAM.fetch_add(1, Ordering::Relaxed));
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, source).unwrap();
+        TempProgram{path}
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+impl Drop for TempProgram {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Loads, checks and compiles a program the same way main does
+pub fn compile(path: &str, options: &compiler::Options) -> Result<Module, String> {
+    let parsed = resolver::load_program(path).map_err(|e| e.desc)?;
+    let module = check_syntax(parsed).map_err(|e| e.desc)?;
+    Ok(module.compile(options))
+}
@@ -13,19 +13,20 @@ pub struct Token {
 
 pub fn tokenise(data: &String) -> Vec<Token> {
 
-    let name_regex = regex::Regex::new(r"^[a-zA-Z_][a-zA-Z_0-9\.]*").unwrap();
+    let name_regex = regex::Regex::new(r"^[a-zA-Z_][a-zA-Z_0-9]*(\.[a-zA-Z_][a-zA-Z_0-9]*)*").unwrap();
     let number_regex = regex::Regex::new(r"^\d+(/\d+)?").unwrap();
     let string_regex = regex::Regex::new(r"^'[^']*'").unwrap();
     let ignore_regex = regex::Regex::new(r"^(([$][^$]*[$])|([ \t\r\f\v]+))").unwrap();
     let newline_regex = regex::Regex::new(r"^\n").unwrap();
     let symbol_regex = regex::Regex::new(&(String::from(r"^(")
-    + r"\+=|\-=|\*=|/="
+    + r"\+=|\-=|\*=|/=|\^="
     + r"|<=|>=|!=|=="
     + r"|~=|=>|//|\*\*"
+    + r"|\.&|\.\||\.\^|\.~|<<|>>"
     + r"|\+|\-|\*|/"
     + r"|=|<|>"
     + r"|\[|\]|\(|\)|\{|\}"
-    + r"|;|~|#|,|&|!|%|\||\^|\."
+    + r"|;|~|#|,|&|!|%|\||\^|\.|\?|:"
     + r")")).unwrap();
 
     let mut ret = Vec::new();
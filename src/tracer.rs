@@ -0,0 +1,170 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{BufWriter, Write};
+
+
+// How many stack items to describe in each entry, and how much of each to show
+pub const STACK_SUMMARY_ITEMS: usize = 3;
+pub const STACK_SUMMARY_CHARS: usize = 60;
+
+
+// Writes one JSON object per executed instruction, optionally only for some functions
+pub struct Tracer {
+    out: BufWriter<Box<dyn Write>>,
+    functions: Option<HashSet<usize>>
+}
+
+// One executed instruction, borrowed from the interpreter state at the time
+pub struct TraceEntry<'a> {
+    pub function: &'a str,
+    pub forwards: bool,
+    pub ip: usize,
+    pub line: Option<usize>,
+    pub instruction: String,
+    pub stack_size: usize,
+    pub stack_top: Vec<String>  // Topmost first
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, functions: Option<HashSet<usize>>) -> Tracer {
+        Tracer{out: BufWriter::new(out), functions}
+    }
+
+    pub fn is_tracing(&self, func_idx: usize) -> bool {
+        self.functions.as_ref().is_none_or(|functions| functions.contains(&func_idx))
+    }
+
+    pub fn write(&mut self, entry: TraceEntry) -> Result<(), String> {
+        let stack_top: Vec<_> = entry.stack_top.iter().map(|item| json_string(&summarise(item))).collect();
+        writeln!(
+            self.out,
+            "{{\"function\": {}, \"direction\": \"{}\", \"ip\": {}, \"line\": {}, \"instruction\": {}, \"stack_size\": {}, \"stack_top\": [{}]}}",
+            json_string(entry.function),
+            if entry.forwards {"forwards"} else {"backwards"},
+            entry.ip,
+            entry.line.map(|line| line.to_string()).unwrap_or_else(|| String::from("null")),
+            json_string(&entry.instruction),
+            entry.stack_size,
+            stack_top.join(", ")
+        ).map_err(|error| format!("Writing the trace failed: {}", error))
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.out.flush().map_err(|error| format!("Writing the trace failed: {}", error))
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracer").field("functions", &self.functions).finish()
+    }
+}
+
+
+fn summarise(item: &str) -> String {
+    if item.chars().count() <= STACK_SUMMARY_CHARS {
+        return String::from(item);
+    }
+    let mut summary: String = item.chars().take(STACK_SUMMARY_CHARS).collect();
+    summary.push_str("...");
+    summary
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::with_capacity(string.len() + 2);
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c)
+        }
+    }
+    json.push('"');
+    json
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use crate::compiler;
+    use crate::interpreter::{Interpreter, Options, OutputBuffer};
+    use crate::testing::{self, TempProgram};
+
+    const PROGRAM: &str = "
+        fn main()() {
+            x = 0;
+            add(x);
+            ~add(x);
+            s = 'text';
+            println(x, s);
+        } ~main()
+
+        fn add(n)() {
+            n += 1;
+        } ~add()";
+
+    // Traces the program, optionally only in one function, giving each line of the trace parsed
+    fn trace(function: Option<&str>) -> Vec<Value> {
+        let file = TempProgram::new(PROGRAM);
+        let program = testing::compile(file.path(), &compiler::Options::default()).unwrap();
+        let options = Options::default();
+        let mut interpreter = Interpreter::new(&program, &options);
+        interpreter.output_to(Box::new(OutputBuffer::new()));
+        let trace = OutputBuffer::new();
+        interpreter.trace_to(Box::new(trace.clone()), function).unwrap();
+        interpreter.run_main(&program).unwrap();
+        trace.contents().lines().map(|line| {
+            serde_json::from_str(line).unwrap_or_else(|error| panic!("{} is not valid JSON: {}", line, error))
+        }).collect()
+    }
+
+    #[test]
+    fn every_line_is_a_json_object() {
+        let entries = trace(None);
+        assert!(!entries.is_empty());
+        for entry in &entries {
+            let keys: Vec<_> = entry.as_object().unwrap().keys().map(String::as_str).collect();
+            assert_eq!(keys.len(), 7);
+            for key in ["function", "direction", "ip", "line", "instruction", "stack_size", "stack_top"] {
+                assert!(keys.contains(&key), "{} is missing {}", entry, key);
+            }
+            assert!(entry["stack_top"].as_array().unwrap().len() <= STACK_SUMMARY_ITEMS);
+        }
+        let functions: HashSet<_> = entries.iter().map(|entry| entry["function"].as_str().unwrap()).collect();
+        assert_eq!(functions, HashSet::from(["main", "add"]));
+        assert!(entries.iter().any(|entry| entry["stack_top"][0] == "text"));
+    }
+
+    #[test]
+    fn tracing_one_function_leaves_out_the_others() {
+        let entries = trace(Some("add"));
+        assert!(!entries.is_empty());
+        assert!(entries.iter().all(|entry| entry["function"] == "add"));
+        // Uncalling it is traced as well as calling it
+        let directions: HashSet<_> = entries.iter().map(|entry| entry["direction"].as_str().unwrap()).collect();
+        assert_eq!(directions, HashSet::from(["forwards", "backwards"]));
+    }
+
+    #[test]
+    fn tracing_a_missing_function_is_an_error() {
+        let file = TempProgram::new(PROGRAM);
+        let program = testing::compile(file.path(), &compiler::Options::default()).unwrap();
+        let options = Options::default();
+        let mut interpreter = Interpreter::new(&program, &options);
+        assert!(interpreter.trace_to(Box::new(OutputBuffer::new()), Some("missing")).is_err());
+    }
+
+    #[test]
+    fn strings_are_escaped_for_json() {
+        let awkward = "quote \" backslash \\ newline \n tab \t bell \u{7} ü";
+        let parsed: Value = serde_json::from_str(&json_string(awkward)).unwrap();
+        assert_eq!(parsed, awkward);
+    }
+}